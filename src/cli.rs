@@ -1,9 +1,12 @@
 use std::path::{Path, PathBuf};
 
 use anyhow::*;
-use clap::{Parser, Subcommand};
+use clap::{Args, Parser, Subcommand};
 use once_cell::sync::Lazy;
 
+use crate::config::{Files, Layout, Period, ValueStyle, WorkTree};
+use crate::key_source::KeySource;
+
 pub fn init() -> Cmd {
     let cmd = Cmd::parse();
     cmd.opt.init_logger();
@@ -26,6 +29,18 @@ pub enum SubCommand {
         /// Display only secret key
         #[clap(long, global = true)]
         only_secret_key: bool,
+        /// Name of the recipient owning the generated key
+        #[clap(long, default_value = "default")]
+        recipient: String,
+        /// Write the secret key to --secret-key-file, encrypted under a passphrase
+        #[clap(long, conflicts_with = "only_secret_key")]
+        passphrase: bool,
+        /// Amber file whose secrets the new file overrides, relative to the new file
+        #[clap(long, value_name = "PATH")]
+        extends: Option<PathBuf>,
+        /// How to store the secrets: file (amber.yaml) or directory (.amber, with a file per secret)
+        #[clap(long, default_value = "file")]
+        layout: Layout,
     },
     /// Add or update a secret
     Encrypt {
@@ -33,22 +48,102 @@ pub enum SubCommand {
         key: String,
         /// Value. If omitted, read from stdin
         value: Option<String>,
+        /// Read the value from this file instead
+        #[clap(long, conflicts_with = "value")]
+        file: Option<PathBuf>,
+        /// Store the value as raw bytes instead of text, e.g. for keystores or certificates
+        #[clap(long, conflicts_with = "value")]
+        binary: bool,
+        #[clap(flatten)]
+        metadata: MetadataOpt,
     },
     /// Generate a new strong secret value, and add it to the repository
     Generate {
         /// Key, must be all capital ASCII characters, digits, and underscores
         key: String,
+        #[clap(flatten)]
+        metadata: MetadataOpt,
     },
     /// Remove a secret
     Remove {
         /// Key, must be all capital ASCII characters, digits, and underscores
         key: String,
     },
+    /// Encrypt many secrets at once, read from a file or the environment
+    Import {
+        /// File to import, - for stdin. Either NAME=value lines, or the JSON or YAML output of
+        /// amber print.
+        #[clap(required_unless_present = "from_env")]
+        file: Option<PathBuf>,
+        /// Format of the file: dotenv, json or yaml. Guessed from the file extension by default.
+        #[clap(long)]
+        format: Option<ImportFormat>,
+        /// Import the environment variables starting with this prefix instead of a file
+        #[clap(long, value_name = "PREFIX", conflicts_with_all = ["file", "format"])]
+        from_env: Option<String>,
+        /// Display what would be imported, without changing the amber file
+        #[clap(long)]
+        dry_run: bool,
+        /// Replace the values of existing secrets
+        #[clap(long, conflicts_with = "skip_existing")]
+        overwrite: bool,
+        /// Leave existing secrets alone, importing only new ones
+        #[clap(long)]
+        skip_existing: bool,
+    },
+    /// Rename a secret, keeping its value and metadata. Requires a secret key since file format version 3.
+    Rename {
+        /// Current name of the secret
+        old: String,
+        /// New name, must be all capital ASCII characters, digits, and underscores
+        new: String,
+    },
+    /// Copy a secret to another amber file or environment, encrypting it for its recipients. Requires a secret key.
+    Copy {
+        /// Name of the secret
+        key: String,
+        /// Amber file to copy to, the current one by default
+        #[clap(long, value_name = "PATH")]
+        to: Option<PathBuf>,
+        /// Environment to copy to, the defaults of the destination by default
+        #[clap(long, value_name = "NAME")]
+        to_env: Option<String>,
+    },
+    /// Edit the secrets in $VISUAL or $EDITOR, then encrypt the changes. Requires a secret key.
+    ///
+    /// Decrypts the secrets of the selected environment to a private temporary file, which is
+    /// overwritten and removed afterwards. Binary values can't be edited this way.
+    Edit {
+        /// Format of the file to edit: dotenv or yaml
+        #[clap(long, default_value = "dotenv")]
+        format: EditFormat,
+    },
     /// Print all of the secrets
     Print {
-        /// Secrets output style, possible values are: setenv, json, yaml, pure. The default is setenv.
+        /// Secrets output style, possible values are: setenv, fish, powershell, nushell, cmd, json, yaml. The default is setenv.
         #[clap(long, default_value = "setenv")]
         style: PrintStyle,
+        /// Also display the amber file each value came from
+        #[clap(long)]
+        show_source: bool,
+    },
+    /// List the secret names and their metadata. Does not require a secret key.
+    List {
+        /// Also display each secret's metadata
+        #[clap(short, long)]
+        long: bool,
+        /// Output names and metadata as JSON
+        #[clap(long, conflicts_with = "long")]
+        json: bool,
+    },
+    /// Report secrets that are expired or due for rotation. Does not require a secret key.
+    Check {
+        /// Report secrets with a deadline within this period, e.g. 30d or 4w
+        #[clap(long, default_value = "30d", value_name = "PERIOD")]
+        warn_within: Period,
+        /// Fail if any secret has a deadline within this period. By default, only past deadlines fail.
+        #[clap(long, default_value = "0d", value_name = "PERIOD")]
+        fail_within: Period,
     },
     /// Run a command with all of the secrets set as environment variables
     Exec {
@@ -57,6 +152,17 @@ pub enum SubCommand {
         /// Command line arguments to pass to the command
         args: Vec<String>,
     },
+    /// Print the value of a single secret
+    Get {
+        /// The key for the secret
+        key: String,
+        /// Do not print a newline after the value. Binary values are always printed as is.
+        #[clap(short = 'n', long)]
+        no_newline: bool,
+        /// Output the key and value as JSON, base64 encoding binary values
+        #[clap(long, conflicts_with = "no_newline")]
+        json: bool,
+    },
     /// Write the contents of a secret to the given file.
     WriteFile {
         /// The key for the secret
@@ -66,12 +172,177 @@ pub enum SubCommand {
         #[clap(long)]
         dest: PathBuf,
     },
+    /// Replace a secret key with a new one and re-encrypt every secret. Requires the current secret key.
+    RotateKey {
+        /// Display only the new secret key
+        #[clap(long)]
+        only_secret_key: bool,
+        /// Recipient whose key should be replaced, defaults to the owner of the current secret key
+        #[clap(long)]
+        recipient: Option<String>,
+    },
+    /// Upgrade the file to the latest file format. Re-encrypting secrets, when needed, requires a secret key.
+    Migrate {
+        /// Only check whether the file uses the latest format, failing if it doesn't
+        #[clap(long)]
+        check: bool,
+    },
+    /// Convert the secrets to another layout, replacing the original. Does not require a secret key.
+    Convert {
+        /// Layout to convert to: file or directory
+        layout: Layout,
+        /// Where to write the converted secrets, by default amber.yaml or .amber next to the original
+        #[clap(long)]
+        dest: Option<PathBuf>,
+    },
+    /// Merge three versions of an amber file secret by secret, as a git merge driver
+    ///
+    /// Writes the result to OURS, failing if both sides changed the same secret differently.
+    MergeDriver {
+        /// Common ancestor, %O in git's merge driver configuration
+        base: PathBuf,
+        /// Our version, replaced with the result, %A in git's merge driver configuration
+        ours: PathBuf,
+        /// Their version, %B in git's merge driver configuration
+        theirs: PathBuf,
+    },
+    /// Render an amber file as sorted NAME=value lines, as a git textconv driver
+    ///
+    /// Decrypts values with the secret key, if provided. Values it can't decrypt are shown as a
+    /// fingerprint of their ciphertexts.
+    Textconv {
+        /// The amber file to render
+        file: PathBuf,
+        /// How to show values: plain, hashed (with a hash keyed by the secret key) or masked
+        #[clap(long, default_value = "plain")]
+        values: ValueStyle,
+    },
+    /// List the commits adding, changing or removing a secret, newest first
+    ///
+    /// Walks the git history of the amber file, up to --rev or HEAD. Values are compared using
+    /// the secret key, if provided.
+    Log {
+        /// Name of the secret
+        key: String,
+        /// Display the value set by each commit, requires a secret key
+        #[clap(long)]
+        show_values: bool,
+    },
+    /// Compare the secrets of two amber files, or environments
+    ///
+    /// Compares names, and values if secret keys for both are provided. Without them, values
+    /// are only compared for versions of the same file.
+    Diff {
+        /// First amber file, a path or REV:PATH to read it from a git revision. REV: alone reads
+        /// the amber file found as usual.
+        left: String,
+        /// Second amber file, like LEFT
+        right: String,
+        /// Environment to compare in the first file, --env by default
+        #[clap(long, value_name = "NAME")]
+        left_env: Option<String>,
+        /// Environment to compare in the second file, --env by default
+        #[clap(long, value_name = "NAME")]
+        right_env: Option<String>,
+        /// File containing the secret key for the first file, the usual secret key by default
+        #[clap(long, value_name = "PATH")]
+        left_secret_key_file: Option<PathBuf>,
+        /// File containing the secret key for the second file, the usual secret key by default
+        #[clap(long, value_name = "PATH")]
+        right_secret_key_file: Option<PathBuf>,
+        /// Output the comparison of every secret as JSON
+        #[clap(long)]
+        json: bool,
+    },
+    /// Manage the recipients able to decrypt the secrets
+    Recipient {
+        #[clap(subcommand)]
+        sub: RecipientCommand,
+    },
+    /// Manage environments, each with their own recipients and secrets overriding the defaults
+    Env {
+        #[clap(subcommand)]
+        sub: EnvCommand,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum EnvCommand {
+    /// List the environments and their recipients
+    List,
+    /// Add an environment with a single recipient, and re-encrypt the defaults for them. Requires a secret key.
+    Add {
+        /// Name of the new environment, e.g. prod
+        name: String,
+        /// Name of the environment's recipient, defaults to the environment name
+        #[clap(long)]
+        recipient: Option<String>,
+        /// Hex encoded public key. If omitted, a new keypair is generated.
+        public_key: Option<String>,
+        /// Display only the generated secret key
+        #[clap(long)]
+        only_secret_key: bool,
+    },
+    /// Remove an environment with its secrets and recipients, and re-encrypt the defaults without them. Requires a secret key.
+    Remove {
+        /// Name of the environment to remove
+        name: String,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum RecipientCommand {
+    /// List the recipients of the selected environment and their public keys
+    List,
+    /// Add a recipient to the selected environment and re-encrypt every secret for them. Requires a secret key.
+    Add {
+        /// Name of the new recipient
+        name: String,
+        /// Hex encoded public key. If omitted, a new keypair is generated.
+        public_key: Option<String>,
+        /// Display only the generated secret key
+        #[clap(long)]
+        only_secret_key: bool,
+    },
+    /// Revoke a recipient and re-encrypt every secret without them. Requires a secret key.
+    Remove {
+        /// Name of the recipient to remove
+        name: String,
+    },
+}
+
+/// Plaintext metadata to set on a secret, left unchanged when omitted
+#[derive(Args, Debug)]
+pub struct MetadataOpt {
+    /// What the secret is for. An empty value removes the description.
+    #[clap(long)]
+    pub description: Option<String>,
+    /// Who is responsible for the secret. An empty value removes the owner.
+    #[clap(long)]
+    pub owner: Option<String>,
+    /// Label for the secret, may be repeated. Replaces existing tags, an empty value removes them.
+    #[clap(long = "tag", value_name = "TAG")]
+    pub tags: Option<Vec<String>>,
+    /// When the value stops working, as a date like 2025-06-30 or an RFC 3339 timestamp. An empty value removes it.
+    #[clap(long, value_name = "TIME")]
+    pub expires_at: Option<String>,
+    /// Rotate the value this long after its last update, e.g. 90d or 12w. An empty value removes it.
+    #[clap(long, value_name = "PERIOD")]
+    pub rotate_after: Option<String>,
 }
 
 #[derive(Parser, Clone, Debug)]
 pub enum PrintStyle {
-    /// Output with `export` prefix, can be evaled in shell.
+    /// Output with `export` prefix, can be evaled in POSIX shells.
     SetEnv,
+    /// Output `set -gx` commands, can be sourced in fish.
+    Fish,
+    /// Output `$env:` assignments, can be evaluated in PowerShell.
+    PowerShell,
+    /// Output `$env.` assignments, can be sourced in nushell.
+    Nushell,
+    /// Output `set` commands for cmd.exe batch files. Values can't contain line breaks.
+    Cmd,
     /// Output as object with `key` and `value` attributes.
     Json,
     /// Output as object with `key` and `value` attributes.
@@ -84,6 +355,10 @@ impl core::str::FromStr for PrintStyle {
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         match s {
             "setenv" => Ok(PrintStyle::SetEnv),
+            "fish" => Ok(PrintStyle::Fish),
+            "powershell" => Ok(PrintStyle::PowerShell),
+            "nushell" => Ok(PrintStyle::Nushell),
+            "cmd" => Ok(PrintStyle::Cmd),
             "json" => Ok(PrintStyle::Json),
             "yaml" => Ok(PrintStyle::Yaml),
             _ => Err(anyhow!("Invalid option for Print command")),
@@ -91,6 +366,62 @@ impl core::str::FromStr for PrintStyle {
     }
 }
 
+/// Format secrets are edited in with `amber edit`
+#[derive(Clone, Copy, Debug)]
+pub enum EditFormat {
+    /// NAME=value lines, double quoting values with special characters
+    Dotenv,
+    /// A mapping of names to values, convenient for multi-line values
+    Yaml,
+}
+
+impl core::str::FromStr for EditFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "dotenv" => Ok(EditFormat::Dotenv),
+            "yaml" => Ok(EditFormat::Yaml),
+            _ => Err(anyhow!("Invalid format {s}, expected dotenv or yaml")),
+        }
+    }
+}
+
+/// Format of the files read by `amber import`
+#[derive(Clone, Copy, Debug)]
+pub enum ImportFormat {
+    /// NAME=value lines, as in .env files
+    Dotenv,
+    /// The output of `amber print --style json`, or an object of names to values
+    Json,
+    /// The output of `amber print --style yaml`, or a mapping of names to values
+    Yaml,
+}
+
+impl ImportFormat {
+    /// Guess the format from a file's extension, defaulting to dotenv
+    pub fn guess(path: &Path) -> Self {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("json") => ImportFormat::Json,
+            Some("yaml" | "yml") => ImportFormat::Yaml,
+            _ => ImportFormat::Dotenv,
+        }
+    }
+}
+
+impl core::str::FromStr for ImportFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "dotenv" => Ok(ImportFormat::Dotenv),
+            "json" => Ok(ImportFormat::Json),
+            "yaml" => Ok(ImportFormat::Yaml),
+            _ => Err(anyhow!("Invalid format {s}, expected dotenv, json or yaml")),
+        }
+    }
+}
+
 static VERSION_SHA: Lazy<String> = Lazy::new(|| {
     let pkgver = env!("CARGO_PKG_VERSION");
     match option_env!("VERGEN_GIT_SHA") {
@@ -101,18 +432,40 @@ static VERSION_SHA: Lazy<String> = Lazy::new(|| {
 
 const DEFAULT_AMBER_YAML: &str = "amber.yaml";
 
+const DEFAULT_AMBER_DIR: &str = ".amber";
+
+/// Default name of the amber file in the given layout
+pub fn default_file_name(layout: Layout) -> &'static str {
+    match layout {
+        Layout::File => DEFAULT_AMBER_YAML,
+        Layout::Directory => DEFAULT_AMBER_DIR,
+    }
+}
+
 /// Utility to store encrypted secrets in version trackable plain text files.
 #[derive(Parser, Debug)]
 pub struct Opt {
     /// Turn on verbose output
     #[clap(short, long, global = true)]
     pub verbose: bool,
-    /// amber.yaml file location
+    /// amber.yaml file location, or .amber directory location for the directory layout
     #[clap(long, global = true, env = "AMBER_YAML")]
     pub amber_yaml: Option<PathBuf>,
     /// Disable masking of secret values during exec
     #[clap(long, global = true)]
     pub unmasked: bool,
+    /// File containing the secret key, e.g. /dev/fd/3. Takes precedence over AMBER_SECRET.
+    #[clap(long, global = true, env = "AMBER_SECRET_FILE")]
+    pub secret_key_file: Option<PathBuf>,
+    /// Key provider plugin to obtain the secret key from, runs the executable amber-key-<NAME>
+    #[clap(long, global = true, env = "AMBER_KEY_PLUGIN", value_name = "NAME")]
+    pub key_plugin: Option<String>,
+    /// Environment to read and write secrets in, falling back to the defaults for reading
+    #[clap(long, global = true, env = "AMBER_ENV", value_name = "NAME")]
+    pub env: Option<String>,
+    /// Read the amber file from a git revision instead of the working tree, like HEAD~2 or a tag
+    #[clap(long, global = true, value_name = "REV")]
+    pub rev: Option<String>,
 }
 
 impl Opt {
@@ -127,23 +480,50 @@ impl Opt {
     }
 
     pub fn find_amber_yaml(&mut self) -> Result<&Path> {
+        self.find_amber_yaml_in(&WorkTree)
+    }
+
+    /// Like [Opt::find_amber_yaml], looking in the working tree or a git revision
+    pub fn find_amber_yaml_in(&mut self, files: &dyn Files) -> Result<&Path> {
         if self.amber_yaml.is_none() {
             for dir in std::env::current_dir()?.ancestors() {
-                let amber_yaml: PathBuf = dir.join(DEFAULT_AMBER_YAML);
-                log::debug!("Checking if file {:?} exists", &amber_yaml);
-                if amber_yaml.exists() {
-                    self.amber_yaml = Some(amber_yaml);
-                    break;
+                let found: Vec<PathBuf> = [DEFAULT_AMBER_YAML, DEFAULT_AMBER_DIR]
+                    .iter()
+                    .map(|name| dir.join(name))
+                    .filter(|amber_yaml| {
+                        log::debug!("Checking if {:?} exists", amber_yaml);
+                        files.exists(amber_yaml)
+                    })
+                    .collect();
+                match found.as_slice() {
+                    [] => continue,
+                    [amber_yaml] => self.amber_yaml = Some(amber_yaml.clone()),
+                    _ => bail!(
+                        "Found both {DEFAULT_AMBER_YAML} and {DEFAULT_AMBER_DIR} in {}, remove one of them",
+                        dir.display()
+                    ),
                 }
+                break;
             }
         }
-        self.amber_yaml
-            .as_deref()
-            .with_context(|| format!("No file named {DEFAULT_AMBER_YAML} found"))
+        self.amber_yaml.as_deref().with_context(|| {
+            format!(
+                "No file named {DEFAULT_AMBER_YAML} or directory named {DEFAULT_AMBER_DIR} found"
+            )
+        })
+    }
+
+    /// Where to look for the secret key
+    pub fn key_source(&self) -> KeySource {
+        KeySource {
+            file: self.secret_key_file.clone(),
+            plugin: self.key_plugin.clone(),
+        }
     }
 
-    pub fn find_amber_yaml_or_default(&mut self) -> &Path {
+    /// The amber file location given by the user, or the default one for new files
+    pub fn find_amber_yaml_or_default(&mut self, layout: Layout) -> &Path {
         self.amber_yaml
-            .get_or_insert_with(|| Path::new(DEFAULT_AMBER_YAML).to_owned())
+            .get_or_insert_with(|| Path::new(default_file_name(layout)).to_owned())
     }
 }
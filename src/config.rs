@@ -1,10 +1,18 @@
-use std::convert::TryInto;
-use std::{collections::HashMap, path::Path};
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::convert::{TryFrom, TryInto};
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
 
 use anyhow::*;
+use base64::Engine;
+use chrono::{DateTime, SubsecRound, Utc};
 use crypto_box::aead::OsRng;
 use crypto_box::{PublicKey, SecretKey};
 use serde::{Deserialize, Serialize};
+
+use crate::key_source::KeySource;
+use hmac::{Hmac, Mac};
 use sha2::Digest;
 use sha2::Sha256;
 
@@ -12,248 +20,2286 @@ use sha2::Sha256;
 pub const SECRET_KEY_ENV: &str = "AMBER_SECRET";
 
 /// Current version of the file format
-const FILE_FORMAT_VERSION: u32 = 1;
+const FILE_FORMAT_VERSION: u32 = 5;
+
+/// Changes introduced by each file format version after the first, in order
+///
+/// Upgrading to a version marked as re-encrypting requires a secret key.
+const FORMAT_CHANGES: [FormatChange; FILE_FORMAT_VERSION as usize - 1] = [
+    FormatChange {
+        version: 2,
+        description: "multiple named recipients",
+        reencrypt: false,
+    },
+    FormatChange {
+        version: 3,
+        description: "ciphertexts bound to their secret name and file",
+        reencrypt: true,
+    },
+    FormatChange {
+        version: 4,
+        description: "keyed digests instead of plaintext SHA-256",
+        reencrypt: true,
+    },
+    FormatChange {
+        version: 5,
+        description: "plaintext metadata and binary values on secrets, and extending other files",
+        reencrypt: false,
+    },
+];
+
+/// Description of a file format version, see [FORMAT_CHANGES]
+#[derive(Debug)]
+pub struct FormatChange {
+    pub version: u32,
+    pub description: &'static str,
+    pub reencrypt: bool,
+}
+
+/// Name given to the only recipient of a version 1 file
+pub const DEFAULT_RECIPIENT: &str = "default";
+
+/// Random identifier of a file, bound to every ciphertext in version 3 and later
+type FileId = [u8; 16];
+
+/// Key for the keyed digests of secret values in version 4 and later
+///
+/// Random per file, and sealed for each recipient, so that digests cannot be used to guess
+/// secret values without a secret key.
+type DigestKey = [u8; 32];
+
+/// File holding everything but the secrets in the directory layout
+const MANIFEST: &str = "amber.yaml";
+
+/// Directory holding one file per default secret in the directory layout
+const SECRETS_DIR: &str = "secrets";
+
+/// Directory holding a directory of secrets per environment in the directory layout
+const ENVIRONMENTS_DIR: &str = "environments";
+
+/// Where amber files are read from, see [WorkTree] and [crate::git::Revision]
+pub trait Files {
+    /// Does a file or directory exist at this path?
+    fn exists(&self, path: &Path) -> bool;
+    fn is_dir(&self, path: &Path) -> bool;
+    fn read(&self, path: &Path) -> Result<Vec<u8>>;
+    /// Names of a directory's entries, and whether they are directories themselves
+    ///
+    /// Empty if the directory doesn't exist.
+    fn list(&self, dir: &Path) -> Result<Vec<(String, bool)>>;
+    /// Identify a file regardless of how its path is spelled, to detect cycles
+    fn identify(&self, path: &Path) -> Result<PathBuf>;
+}
+
+/// The files on disk
+pub struct WorkTree;
+
+impl Files for WorkTree {
+    fn exists(&self, path: &Path) -> bool {
+        path.exists()
+    }
+
+    fn is_dir(&self, path: &Path) -> bool {
+        path.is_dir()
+    }
+
+    fn read(&self, path: &Path) -> Result<Vec<u8>> {
+        Ok(fs_err::read(path)?)
+    }
+
+    fn list(&self, dir: &Path) -> Result<Vec<(String, bool)>> {
+        let mut entries = Vec::new();
+        if !dir.is_dir() {
+            return Ok(entries);
+        }
+        for entry in fs_err::read_dir(dir)? {
+            let entry = entry?;
+            let is_dir = entry.file_type()?.is_dir();
+            entries.push((entry.file_name().to_string_lossy().into_owned(), is_dir));
+        }
+        Ok(entries)
+    }
+
+    fn identify(&self, path: &Path) -> Result<PathBuf> {
+        Ok(fs_err::canonicalize(path)?)
+    }
+}
+
+/// How the secrets are stored on disk
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Layout {
+    /// A single YAML file
+    #[default]
+    File,
+    /// A directory holding a manifest with the recipients, and one YAML file per secret
+    ///
+    /// Spreading the secrets over many files avoids merge conflicts when several people
+    /// change secrets at once.
+    Directory,
+}
+
+impl std::str::FromStr for Layout {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "file" => Ok(Layout::File),
+            "directory" => Ok(Layout::Directory),
+            _ => Err(anyhow!("Invalid layout {s:?}, expected file or directory")),
+        }
+    }
+}
+
+impl std::fmt::Display for Layout {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Layout::File => "file",
+            Layout::Directory => "directory",
+        })
+    }
+}
 
 /// Raw version of [Config], the thing actually serialized/deserialized
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 #[serde(deny_unknown_fields)]
 struct ConfigRaw {
     /// Version of the file format represented here
     file_format_version: u32,
 
-    /// Hex encoded public key
-    public_key: String,
+    /// Hex encoded random identifier of this file, version 3 and later
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    file_id: Option<String>,
+
+    /// Another amber file whose secrets this one overrides, relative to this file
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    extends: Option<PathBuf>,
+
+    /// Hex encoded public key, only used by version 1
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    public_key: Option<String>,
+
+    /// Everyone able to decrypt the secrets, version 2 and later
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    recipients: Vec<RecipientRaw>,
 
     /// Use a Vec instead of a HashMap to get guaranteed order in the output for
-    /// minimal deltas
+    /// minimal deltas. Absent from the manifest of the directory layout.
+    #[serde(default)]
+    secrets: Vec<SecretRaw>,
+
+    /// Named environments overriding the secrets above, version 4 and later
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    environments: Vec<EnvironmentRaw>,
+}
+
+/// Raw version of an [Environment]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+struct EnvironmentRaw {
+    name: String,
+    /// Recipients only able to read the defaults and this environment's secrets
+    recipients: Vec<RecipientRaw>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
     secrets: Vec<SecretRaw>,
 }
 
+/// Raw version of a single recipient
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+struct RecipientRaw {
+    name: String,
+    /// Hex encoded public key
+    public_key: String,
+    /// Hex encoded digest key, sealed for this recipient. Version 4 and later.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    digest_key: Option<String>,
+}
+
 /// Raw version of [Secret], allowing for consistent ordering
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 #[serde(deny_unknown_fields)]
 struct SecretRaw {
     name: String,
-    sha256: String,
-    cipher: String,
+    /// Absent for text values
+    #[serde(default, skip_serializing_if = "ValueKind::is_text")]
+    kind: ValueKind,
+    /// See [Metadata]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    description: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    owner: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    created_at: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    updated_at: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    tags: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    expires_at: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    rotate_after: Option<Period>,
+    /// Plain SHA-256 of the value, only used before version 4
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    sha256: Option<String>,
+    /// Keyed digest of the name and value, version 4 and later
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    digest: Option<String>,
+    /// Ciphertext for the single public key, only used by version 1
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    cipher: Option<String>,
+    /// Ciphertext for each recipient, keyed by recipient name
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    ciphers: BTreeMap<String, String>,
 }
 
 /// Config file
 #[derive(Debug)]
 pub struct Config {
-    /// Public key in hex
-    public_key: PublicKey,
-    /// Encrypted secrets
+    /// File format version this config was loaded from, and will be saved as
+    version: u32,
+    /// Identifier bound to each ciphertext along with the secret name, version 3 and later
+    file_id: Option<FileId>,
+    /// Another amber file whose secrets this one overrides, see [crate::layers]
+    extends: Option<PathBuf>,
+    /// Public keys of everyone able to decrypt, keyed by recipient name, in every environment
+    recipients: BTreeMap<String, PublicKey>,
+    /// The digest key sealed for each recipient, version 4 and later
+    digest_keys: BTreeMap<String, Vec<u8>>,
+    /// Default secrets, encrypted for every recipient
+    secrets: HashMap<String, Secret>,
+    /// Named environments, overriding the default secrets
+    environments: BTreeMap<String, Environment>,
+    /// Environment whose secrets are read and written, None for the defaults
+    selected: Option<String>,
+    /// How the config was loaded, and will be saved
+    layout: Layout,
+}
+
+/// A set of secrets overriding the defaults, e.g. for production
+#[derive(Debug)]
+struct Environment {
+    /// Names of the recipients belonging to this environment, see [Config::recipients]
+    recipients: BTreeSet<String>,
+    /// Secrets encrypted for this environment's recipients only
     secrets: HashMap<String, Secret>,
 }
 
+/// The secrets an operation applies to: the defaults, or those of the named environment
+type Scope<'a> = Option<&'a str>;
+
+/// Decrypted secrets, with the environment they belong to
+type Plains = Vec<(Option<String>, String, Vec<u8>)>;
+
 /// The contents of an individual secret, still encrypted
-#[derive(Serialize, Deserialize, Debug)]
-#[serde(deny_unknown_fields)]
+#[derive(Debug)]
 struct Secret {
     /// Digest of the plaintext, to avoid unnecessary updates and minimize diffs
-    sha256: [u8; 32],
-    /// Ciphertext encrypted with our public key
-    cipher: Vec<u8>,
+    ///
+    /// A plain SHA-256 before version 4. Afterwards keyed with the [DigestKey], and missing
+    /// if the value was encrypted without access to a secret key.
+    digest: Option<[u8; 32]>,
+    /// Ciphertext encrypted with each recipient's public key
+    ciphers: BTreeMap<String, Vec<u8>>,
+    /// Whether the plaintext is text or arbitrary bytes
+    kind: ValueKind,
+    /// Plaintext information about the secret
+    metadata: Metadata,
+}
+
+/// How [Config::render] shows secret values
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValueStyle {
+    /// The decrypted values
+    Plain,
+    /// A hash of each value, keyed with the secret key so that it can't be used to guess values
+    Hashed,
+    /// A fingerprint of the ciphertexts only, not requiring a secret key
+    Masked,
+}
+
+impl std::str::FromStr for ValueStyle {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "plain" => Ok(ValueStyle::Plain),
+            "hashed" => Ok(ValueStyle::Hashed),
+            "masked" => Ok(ValueStyle::Masked),
+            _ => Err(anyhow!(
+                "Invalid value style {s:?}, expected plain, hashed or masked"
+            )),
+        }
+    }
+}
+
+/// What kind of data a secret holds
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ValueKind {
+    /// UTF-8 text, usable as an environment variable
+    #[default]
+    Text,
+    /// Arbitrary bytes, such as keystores or certificates in DER format
+    Binary,
+}
+
+impl ValueKind {
+    pub fn is_text(&self) -> bool {
+        *self == ValueKind::Text
+    }
+}
+
+/// Optional plaintext information about a secret, readable without a secret key
+#[derive(Serialize, Debug, Default, Clone, PartialEq, Eq)]
+pub struct Metadata {
+    /// What the secret is for
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// Who is responsible for the secret
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub owner: Option<String>,
+    /// When the secret was first encrypted
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<DateTime<Utc>>,
+    /// When the secret's value last changed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub updated_at: Option<DateTime<Utc>>,
+    /// Free form labels
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    /// When the value stops working, e.g. the expiry date of an API token
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<DateTime<Utc>>,
+    /// How long after its last update the value should be rotated
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rotate_after: Option<Period>,
+}
+
+impl Metadata {
+    pub fn is_empty(&self) -> bool {
+        self == &Metadata::default()
+    }
+
+    /// When the value is due for rotation according to [Metadata::rotate_after]
+    ///
+    /// Unknown if the secret has no timestamps, e.g. because it was added by an older amber, and
+    /// never if the period reaches beyond the dates we can represent.
+    pub fn rotation_due(&self) -> Option<DateTime<Utc>> {
+        let period = self.rotate_after.as_ref()?;
+        let updated_at = self.updated_at.or(self.created_at)?;
+        period.after(updated_at)
+    }
+}
+
+/// A number of days or weeks, written like `90d` or `12w`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(try_from = "String", into = "String")]
+pub struct Period {
+    count: u32,
+    unit: PeriodUnit,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PeriodUnit {
+    Days,
+    Weeks,
+}
+
+impl Period {
+    pub fn duration(&self) -> chrono::Duration {
+        match self.unit {
+            PeriodUnit::Days => chrono::Duration::days(self.count.into()),
+            PeriodUnit::Weeks => chrono::Duration::weeks(self.count.into()),
+        }
+    }
+
+    /// The given time plus this period, if representable
+    pub fn after(&self, time: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        time.checked_add_signed(self.duration())
+    }
+}
+
+impl std::str::FromStr for Period {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid =
+            || anyhow!("Invalid period {s:?}, expected a number of days or weeks like 90d or 12w");
+        let (count, unit) = if let Some(count) = s.strip_suffix('d') {
+            (count, PeriodUnit::Days)
+        } else if let Some(count) = s.strip_suffix('w') {
+            (count, PeriodUnit::Weeks)
+        } else {
+            return Err(invalid());
+        };
+        let count = count.parse().map_err(|_| invalid())?;
+        Ok(Period { count, unit })
+    }
+}
+
+impl std::fmt::Display for Period {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let unit = match self.unit {
+            PeriodUnit::Days => 'd',
+            PeriodUnit::Weeks => 'w',
+        };
+        write!(f, "{}{}", self.count, unit)
+    }
+}
+
+impl TryFrom<String> for Period {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> Result<Self> {
+        s.parse()
+    }
+}
+
+impl From<Period> for String {
+    fn from(period: Period) -> Self {
+        period.to_string()
+    }
+}
+
+/// Parse a point in time given either as a date like `2025-06-30`, or in RFC 3339 format
+///
+/// Dates refer to midnight UTC at the start of that day.
+pub fn parse_time(s: &str) -> Result<DateTime<Utc>> {
+    if let Some(date) = chrono::NaiveDate::parse_from_str(s, "%Y-%m-%d")
+        .ok()
+        .and_then(|date| date.and_hms_opt(0, 0, 0))
+    {
+        return Ok(date.and_utc());
+    }
+    DateTime::parse_from_rfc3339(s)
+        .map(|time| time.with_timezone(&Utc))
+        .with_context(|| {
+            format!("Invalid time {s:?}, expected a date like 2025-06-30 or an RFC 3339 timestamp")
+        })
 }
 
 impl Config {
-    /// Create a new keypair and config file
-    pub fn new() -> (SecretKey, Self) {
+    /// Create a new keypair and config file, with a single recipient
+    pub fn new(recipient: &str) -> Result<(SecretKey, Self)> {
+        validate_recipient(recipient)?;
         let secret_key = SecretKey::generate(&mut OsRng);
-        let config = Config {
-            public_key: secret_key.public_key(),
+        let mut recipients = BTreeMap::new();
+        recipients.insert(recipient.to_owned(), secret_key.public_key());
+        let mut config = Config {
+            version: FILE_FORMAT_VERSION,
+            file_id: Some(new_file_id()),
+            extends: None,
+            recipients,
+            digest_keys: BTreeMap::new(),
             secrets: HashMap::new(),
+            environments: BTreeMap::new(),
+            selected: None,
+            layout: Layout::File,
         };
-        (secret_key, config)
+        config.digest_keys = config.seal(None, &new_digest_key())?;
+        Ok((secret_key, config))
     }
 
     fn from_raw(raw: ConfigRaw) -> Result<Self> {
+        let version = raw.file_format_version;
         ensure!(
-            raw.file_format_version == FILE_FORMAT_VERSION,
-            "Unsupported file format detected. Detected format is {}, we only support {}.",
-            raw.file_format_version,
+            version <= FILE_FORMAT_VERSION,
+            "File format version {} was written by a newer version of amber, we only support 1 through {}. Please upgrade amber.",
+            version,
             FILE_FORMAT_VERSION
         );
-        let public_key: [u8; 32] = hex::decode(&raw.public_key)
-            .ok()
-            .context("Public key is not hex")?
-            .try_into()
-            .map_err(|_| anyhow!("Invalid Public key"))?;
+        let mut digest_keys = BTreeMap::new();
+        let mut recipients = match version {
+            1 => {
+                ensure!(
+                    raw.recipients.is_empty(),
+                    "Version 1 files cannot contain recipients"
+                );
+                let public_key = raw
+                    .public_key
+                    .context("Version 1 files must contain a public key")?;
+                let mut recipients = BTreeMap::new();
+                recipients.insert(DEFAULT_RECIPIENT.to_owned(), parse_public_key(&public_key)?);
+                recipients
+            }
+            2..=FILE_FORMAT_VERSION => {
+                ensure!(
+                    raw.public_key.is_none(),
+                    "Only version 1 files contain a top level public key, use recipients instead"
+                );
+                ensure!(!raw.recipients.is_empty(), "No recipients found");
+                let mut recipients = BTreeMap::new();
+                parse_recipients(version, raw.recipients, &mut recipients, &mut digest_keys)?;
+                recipients
+            }
+            _ => bail!("Invalid file format version {}", version),
+        };
 
-        let public_key = PublicKey::from(public_key);
+        let file_id = match raw.file_id {
+            Some(file_id) => {
+                ensure!(
+                    version >= 3,
+                    "Only version 3 and later files contain a file ID"
+                );
+                let file_id: FileId = hex::decode(file_id)
+                    .ok()
+                    .context("File ID is not hex")?
+                    .try_into()
+                    .map_err(|_| anyhow!("Invalid file ID"))?;
+                Some(file_id)
+            }
+            None => {
+                ensure!(version < 3, "No file ID found");
+                None
+            }
+        };
 
-        let mut secrets = HashMap::new();
+        ensure!(
+            version >= 5 || raw.extends.is_none(),
+            "Only version 5 and later files extend other files"
+        );
 
-        for pair in raw.secrets.into_iter().map(Secret::from_raw) {
-            let (key, secret) = pair?;
+        let mut environments = BTreeMap::new();
+        for environment in raw.environments {
             ensure!(
-                !secrets.contains_key(&key),
-                "Duplicated secret key: {}",
-                key
+                version >= 4,
+                "Only version 4 and later files contain environments"
+            );
+            let EnvironmentRaw {
+                name,
+                recipients: raw_recipients,
+                secrets: raw_secrets,
+            } = environment;
+            let environment: Result<Environment> = (|| {
+                validate_environment(&name)?;
+                ensure!(!raw_recipients.is_empty(), "No recipients found");
+                let members =
+                    parse_recipients(version, raw_recipients, &mut recipients, &mut digest_keys)?;
+                let secrets = parse_secrets(raw_secrets, version, &members, true)?;
+                Ok(Environment {
+                    recipients: members,
+                    secrets,
+                })
+            })();
+            let environment = environment.with_context(|| format!("Invalid environment {name}"))?;
+            ensure!(
+                environments.insert(name.clone(), environment).is_none(),
+                "Duplicated environment: {}",
+                name
             );
-            let old = secrets.insert(key, secret);
-            assert!(old.is_none());
         }
+
+        // Default secrets are encrypted for everyone
+        let everyone = recipients.keys().cloned().collect();
+        let secrets = parse_secrets(raw.secrets, version, &everyone, false)?;
         Ok(Config {
-            public_key,
+            version,
+            file_id,
+            extends: raw.extends,
+            recipients,
+            digest_keys,
             secrets,
+            environments,
+            selected: None,
+            layout: Layout::File,
         })
     }
 
     fn to_raw(&self) -> ConfigRaw {
-        let mut secrets: Vec<SecretRaw> = self
-            .secrets
+        let recipient_raw = |name: &String| RecipientRaw {
+            name: name.clone(),
+            public_key: hex::encode(&self.recipients[name]),
+            digest_key: self.digest_keys.get(name).map(hex::encode),
+        };
+        let (public_key, recipients) = if self.version == 1 {
+            let public_key = self
+                .recipients
+                .values()
+                .next()
+                .expect("Version 1 files have exactly one recipient");
+            (Some(hex::encode(public_key)), vec![])
+        } else {
+            let recipients = self.members(None).into_iter().map(recipient_raw).collect();
+            (None, recipients)
+        };
+        let environments = self
+            .environments
             .iter()
-            .map(|(key, value)| SecretRaw {
-                name: key.clone(),
-                sha256: hex::encode(value.sha256),
-                cipher: hex::encode(&value.cipher),
+            .map(|(name, environment)| EnvironmentRaw {
+                name: name.clone(),
+                recipients: environment.recipients.iter().map(recipient_raw).collect(),
+                secrets: secrets_to_raw(&environment.secrets, self.version),
             })
             .collect();
-        secrets.sort_unstable_by(|x, y| x.name.cmp(&y.name));
         ConfigRaw {
-            file_format_version: FILE_FORMAT_VERSION,
-            public_key: hex::encode(&self.public_key),
-            secrets,
+            file_format_version: self.version,
+            file_id: self.file_id.map(hex::encode),
+            extends: self.extends.clone(),
+            public_key,
+            recipients,
+            secrets: secrets_to_raw(&self.secrets, self.version),
+            environments,
         }
     }
 
+    /// Load a config file, or a directory in the [Layout::Directory] layout
     pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
-        let path = path.as_ref();
+        Self::load_from(&WorkTree, path.as_ref())
+    }
+
+    /// Like [Config::load], reading from the working tree or a git revision
+    pub fn load_from(files: &dyn Files, path: &Path) -> Result<Self> {
         let res: Result<Self> = (|| {
-            let mut file = fs_err::File::open(path)?;
-            let config = serde_yaml::from_reader(&mut file)?;
-            Config::from_raw(config)
+            let (layout, raw) = if files.is_dir(path) {
+                (Layout::Directory, read_directory(files, path)?)
+            } else {
+                (Layout::File, serde_yaml::from_slice(&files.read(path)?)?)
+            };
+            let mut config = Config::from_raw(raw)?;
+            config.layout = layout;
+            Ok(config)
         })();
         res.with_context(|| format!("Unable to read file {}", path.display()))
     }
 
+    /// Save the config file, atomically replacing any existing file
+    ///
+    /// In the directory layout, each file is replaced atomically, and only if it changed.
     pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
         let path = path.as_ref();
-        let res: Result<()> = (|| {
-            let parent = path.parent().context("File must have a parent directory")?;
-            fs_err::create_dir_all(parent).context("Unable to create parent directory")?;
-            let mut file = fs_err::File::create(path)?;
-            serde_yaml::to_writer(&mut file, &self.to_raw())?;
-            Ok(())
+        let res: Result<()> = (|| match self.layout {
+            Layout::File => write_atomic(path, serde_yaml::to_string(&self.to_raw())?.as_bytes()),
+            Layout::Directory => write_directory(path, self.to_raw()),
         })();
         res.with_context(|| format!("Unable to write file {}", path.display()))
     }
 
-    /// Encrypt a new value, replacing as necessary
-    pub fn encrypt(&mut self, key: String, value: &str) -> Result<()> {
-        let mut hasher = Sha256::new();
-        hasher.update(value);
-        let hash = hasher.finalize_reset().into();
-        if let Some(old_secret) = self.secrets.get(&key) {
-            if old_secret.sha256 == hash {
-                log::info!("New value matches old value, doing nothing");
-                return Ok(());
-            } else {
-                log::warn!("Overwriting old secret value");
-            }
+    pub fn layout(&self) -> Layout {
+        self.layout
+    }
+
+    /// Change the layout used by [Config::save]
+    pub fn set_layout(&mut self, layout: Layout) {
+        self.layout = layout;
+    }
+
+    /// The amber file this one extends, relative to this file
+    pub fn extends(&self) -> Option<&Path> {
+        self.extends.as_deref()
+    }
+
+    pub fn set_extends(&mut self, extends: Option<PathBuf>) {
+        self.extends = extends;
+    }
+
+    /// Does the file contain the given environment?
+    pub fn has_environment(&self, environment: &str) -> bool {
+        self.environments.contains_key(environment)
+    }
+
+    /// Select the environment whose secrets are read and written, or None for the defaults
+    ///
+    /// Reading an environment's secrets falls back to the defaults for keys it doesn't override.
+    pub fn select_environment(&mut self, environment: Option<String>) -> Result<()> {
+        if let Some(environment) = &environment {
+            ensure!(
+                self.environments.contains_key(environment),
+                "No environment named {environment}"
+            );
         }
+        self.selected = environment;
+        Ok(())
+    }
 
-        let cipher = self
-            .public_key
-            .seal(&mut OsRng, value.as_bytes())
-            .map_err(|_| anyhow!("Error during encryption"))?;
+    /// Encrypt a new value in the selected environment, replacing as necessary
+    ///
+    /// Since version 4, the secret key is needed to compute the digest used to detect unchanged
+    /// values. Without it, the value is always re-encrypted and stored without a digest.
+    pub fn encrypt(
+        &mut self,
+        key: String,
+        value: &[u8],
+        kind: ValueKind,
+        secret_key: Option<&SecretKey>,
+    ) -> Result<()> {
+        ensure!(
+            kind == ValueKind::Binary || std::str::from_utf8(value).is_ok(),
+            "Text values must be valid UTF-8"
+        );
+        ensure!(
+            kind == ValueKind::Text || self.version >= 5,
+            "Binary values require file format version 5, this file uses version {}. Run amber migrate to upgrade it.",
+            self.version
+        );
+        let selected = self.selected.clone();
+        let scope = selected.as_deref();
+        let digest_key = match secret_key {
+            Some(secret_key) => self.digest_key(secret_key)?,
+            None => None,
+        };
+        let digest = self.digest(scope, &key, value, digest_key.as_ref());
+        if let Some(old_secret) = self.secrets_in_mut(scope).get_mut(&key) {
+            if old_secret.kind != kind {
+                log::info!("Changing the kind of secret {key} to {kind:?}");
+                old_secret.kind = kind;
+            }
+        }
+        if let Some(old_secret) = self.secrets_in(scope).get(&key) {
+            let unchanged = match (old_secret.digest, digest) {
+                (Some(old_digest), Some(digest)) => Some(old_digest == digest),
+                // Without digests to compare, compare the plaintext instead
+                _ => match secret_key.filter(|secret_key| self.can_decrypt(scope, secret_key)) {
+                    Some(secret_key) => {
+                        Some(self.decrypt_secret(scope, &key, old_secret, secret_key)? == value)
+                    }
+                    None => None,
+                },
+            };
+            match unchanged {
+                Some(true) => {
+                    if old_secret.digest.is_none() && digest.is_some() {
+                        log::info!("New value matches old value, only adding its digest");
+                        if let Some(old_secret) = self.secrets_in_mut(scope).get_mut(&key) {
+                            old_secret.digest = digest;
+                        }
+                    } else {
+                        log::info!("New value matches old value, doing nothing");
+                    }
+                    return Ok(());
+                }
+                Some(false) => log::warn!("Overwriting old secret value"),
+                None => log::warn!(
+                    "No secret key available to compare with the old value, overwriting it"
+                ),
+            }
+        }
 
-        self.secrets.insert(
+        let ciphers = self.seal(scope, &self.bind(scope, &key, value))?;
+        let old_metadata = self
+            .secrets_in_mut(scope)
+            .remove(&key)
+            .map(|old| old.metadata);
+        // Older versions have no metadata, not even timestamps
+        let metadata = if self.supports_metadata() {
+            let now = Utc::now().trunc_subsecs(0);
+            Metadata {
+                updated_at: Some(now),
+                ..old_metadata.unwrap_or_else(|| Metadata {
+                    created_at: Some(now),
+                    ..Metadata::default()
+                })
+            }
+        } else {
+            Metadata::default()
+        };
+        self.secrets_in_mut(scope).insert(
             key,
             Secret {
-                cipher,
-                sha256: hash,
+                ciphers,
+                digest,
+                kind,
+                metadata,
             },
         );
         Ok(())
     }
 
-    /// Remove a value, if present
-    pub fn remove(&mut self, key: &str) {
-        if self.secrets.remove(key).is_none() {
-            log::warn!("Asked to remove non-present secret {}, doing nothing", key);
+    /// The metadata of every secret visible in the selected environment, sorted by name
+    pub fn metadata(&self) -> Vec<(&String, &Metadata)> {
+        self.visible_secrets()
+            .into_iter()
+            .map(|(_, key, secret)| (key, &secret.metadata))
+            .collect()
+    }
+
+    /// Can secrets carry metadata? Only since version 5.
+    pub fn supports_metadata(&self) -> bool {
+        self.version >= 5
+    }
+
+    /// Modify the metadata of an existing secret in the selected environment
+    pub fn metadata_mut(&mut self, key: &str) -> Result<&mut Metadata> {
+        ensure!(
+            self.supports_metadata(),
+            "Secret metadata requires file format version 5, this file uses version {}. Run amber migrate to upgrade it.",
+            self.version
+        );
+        let selected = self.selected.clone();
+        self.secrets_in_mut(selected.as_deref())
+            .get_mut(key)
+            .map(|secret| &mut secret.metadata)
+            .with_context(|| format!("Key does not exist: {key}"))
+    }
+
+    /// Compute the digest of a value, see [Secret::digest]
+    ///
+    /// Secrets of environments have none: every recipient knows the digest key, which would let
+    /// recipients of other environments test guesses of their values.
+    fn digest(
+        &self,
+        scope: Scope,
+        key: &str,
+        value: &[u8],
+        digest_key: Option<&DigestKey>,
+    ) -> Option<[u8; 32]> {
+        if self.version < 4 {
+            let mut hasher = Sha256::new();
+            hasher.update(value);
+            Some(hasher.finalize_reset().into())
+        } else if scope.is_some() {
+            None
+        } else {
+            let mut mac = Hmac::<Sha256>::new_from_slice(digest_key?)
+                .expect("HMAC accepts keys of any length");
+            mac.update(&(key.len() as u32).to_be_bytes());
+            mac.update(key.as_bytes());
+            mac.update(value);
+            Some(mac.finalize().into_bytes().into())
         }
     }
 
-    /// Get the secret key from the environment variable
+    /// Unseal the digest key with the given secret key, if the file format has one
+    fn digest_key(&self, secret_key: &SecretKey) -> Result<Option<DigestKey>> {
+        if self.version < 4 {
+            return Ok(None);
+        }
+        let recipient = self.recipient_for(secret_key)?;
+        let sealed = self
+            .digest_keys
+            .get(recipient)
+            .with_context(|| format!("No digest key for recipient {recipient}"))?;
+        let digest_key: DigestKey = secret_key
+            .unseal(&sealed[..])
+            .map_err(|_| anyhow!("Unable to decrypt digest key"))?
+            .try_into()
+            .map_err(|_| anyhow!("Invalid digest key"))?;
+        Ok(Some(digest_key))
+    }
+
+    /// Tie a plaintext value to this file and the secret's name, if the file format supports it
     ///
-    /// Validates that it matches up with the public key
-    pub fn load_secret_key(&self) -> Result<SecretKey> {
-        (|| {
-            let hex = std::env::var(SECRET_KEY_ENV)?;
-            let bs: [u8; 32] = hex::decode(hex)
-                .ok()
-                .context("Invalid hex encoding")?
-                .try_into()
-                .map_err(|_| anyhow!("Invalid secret key"))?;
-            let secret: SecretKey = SecretKey::from(bs);
-            ensure!(
-                secret.public_key() == self.public_key,
-                "Secret key does not match config file's public key"
-            );
-            Ok(secret)
-        })()
-        .with_context(|| {
-            format!("Error loading secret key from environment variable {SECRET_KEY_ENV}")
-        })
+    /// This prevents ciphertexts from being moved to a different secret or file unnoticed.
+    fn bind(&self, scope: Scope, key: &str, value: &[u8]) -> Vec<u8> {
+        match &self.file_id {
+            None => value.to_vec(),
+            Some(file_id) => {
+                let name = bound_name(scope, key);
+                let mut plain = Vec::with_capacity(file_id.len() + 4 + name.len() + value.len());
+                plain.extend_from_slice(file_id);
+                plain.extend_from_slice(&(name.len() as u32).to_be_bytes());
+                plain.extend_from_slice(name.as_bytes());
+                plain.extend_from_slice(value);
+                plain
+            }
+        }
     }
 
-    /// Iterate over the secrets
-    pub fn iter_secrets<'a>(
-        &'a self,
-        secret_key: &'a SecretKey,
-    ) -> impl Iterator<Item = Result<(&'a String, String)>> {
-        self.secrets
+    /// Encrypt a plaintext for every recipient able to read the given scope
+    fn seal(&self, scope: Scope, plain: &[u8]) -> Result<BTreeMap<String, Vec<u8>>> {
+        self.recipients
             .iter()
-            .map(move |(key, secret)| secret.decrypt(secret_key, key).map(|plain| (key, plain)))
+            .filter(|(recipient, _)| self.in_scope(scope, recipient))
+            .map(|(recipient, public_key)| {
+                let cipher = public_key
+                    .seal(&mut OsRng, plain)
+                    .map_err(|_| anyhow!("Error during encryption"))?;
+                Ok((recipient.clone(), cipher))
+            })
+            .collect()
     }
 
-    /// Look up a specific secret value
-    pub(crate) fn get_secret(&self, key: &str, secret_key: &SecretKey) -> Result<String> {
-        self.secrets
-            .get(key)
-            .with_context(|| format!("Key does not exist: {key}"))
-            .and_then(|secret| secret.decrypt(secret_key, key))
+    /// Can the given recipient decrypt secrets of the given scope?
+    ///
+    /// Default secrets are encrypted for everyone, so that every environment can fall back to them.
+    fn in_scope(&self, scope: Scope, recipient: &str) -> bool {
+        match scope {
+            None => self.recipients.contains_key(recipient),
+            Some(environment) => self.environments[environment]
+                .recipients
+                .contains(recipient),
+        }
     }
-}
 
-impl Secret {
-    fn from_raw(raw: SecretRaw) -> Result<(String, Self)> {
-        let digest: [u8; 32] = hex::decode(&raw.sha256)
-            .ok()
-            .context("Non-hex sha256")?
-            .try_into()
-            .map_err(|_| anyhow!("Error parsing into digest"))?;
-        Ok((
-            raw.name,
-            Secret {
-                sha256: digest,
-                cipher: hex::decode(&raw.cipher)
-                    .ok()
-                    .context("Non-hex ciphertext")?,
-            },
-        ))
+    /// Can the given secret key decrypt the secrets of the selected environment?
+    pub fn accepts(&self, secret_key: &SecretKey) -> bool {
+        self.can_decrypt(self.selected.as_deref(), secret_key)
     }
 
-    /// Decrypt this secret, key is used for error message displays only
-    fn decrypt(&self, secret_key: &SecretKey, key: &str) -> Result<String> {
-        (|| {
-            let plain = secret_key
-                .unseal(&self.cipher[..])
-                .map_err(|_| anyhow!("Unable to decrypt secret"))?;
-            let mut hasher = Sha256::new();
-            hasher.update(&plain);
-            let digest: [u8; 32] = hasher.finalize_reset().into();
-            ensure!(
-                digest == self.sha256,
-                "Hash mismatch, expected {}, received {}",
-                hex::encode(self.sha256),
-                hex::encode(digest)
-            );
-            String::from_utf8(plain.to_vec()).context("Invalid UTF-8 encoding")
-        })()
-        .with_context(|| format!("Error while decrypting secret named {key}"))
+    fn can_decrypt(&self, scope: Scope, secret_key: &SecretKey) -> bool {
+        self.recipient_for(secret_key)
+            .map_or(false, |recipient| self.in_scope(scope, recipient))
+    }
+
+    /// Recipients belonging to the defaults or an environment
+    fn members(&self, scope: Scope) -> BTreeSet<&String> {
+        match scope {
+            None => self
+                .recipients
+                .keys()
+                .filter(|name| self.environment_of(name).is_none())
+                .collect(),
+            Some(environment) => self.environments[environment].recipients.iter().collect(),
+        }
+    }
+
+    /// The environment a recipient belongs to, None for the defaults
+    fn environment_of(&self, recipient: &str) -> Option<&str> {
+        self.environments
+            .iter()
+            .find(|(_, environment)| environment.recipients.contains(recipient))
+            .map(|(name, _)| name.as_str())
+    }
+
+    fn secrets_in(&self, scope: Scope) -> &HashMap<String, Secret> {
+        match scope {
+            None => &self.secrets,
+            Some(environment) => &self.environments[environment].secrets,
+        }
+    }
+
+    fn secrets_in_mut(&mut self, scope: Scope) -> &mut HashMap<String, Secret> {
+        match scope {
+            None => &mut self.secrets,
+            Some(environment) => {
+                &mut self
+                    .environments
+                    .get_mut(environment)
+                    .expect("Unknown environment")
+                    .secrets
+            }
+        }
+    }
+
+    /// Secrets of the selected environment, falling back to the defaults, sorted by name
+    fn visible_secrets(&self) -> Vec<(Scope, &String, &Secret)> {
+        let mut visible: BTreeMap<&String, (Scope, &Secret)> = self
+            .secrets
+            .iter()
+            .map(|(key, secret)| (key, (None, secret)))
+            .collect();
+        if let Some(environment) = self.selected.as_deref() {
+            for (key, secret) in self.secrets_in(Some(environment)) {
+                visible.insert(key, (Some(environment), secret));
+            }
+        }
+        visible
+            .into_iter()
+            .map(|(key, (scope, secret))| (scope, key, secret))
+            .collect()
+    }
+
+    /// Find a secret visible in the selected environment
+    fn lookup(&self, key: &str) -> Option<(Scope, &Secret)> {
+        let selected = self.selected.as_deref();
+        selected
+            .and_then(|environment| {
+                self.secrets_in(Some(environment))
+                    .get(key)
+                    .map(|secret| (selected, secret))
+            })
+            .or_else(|| self.secrets.get(key).map(|secret| (None, secret)))
+    }
+
+    /// Remove a value from the selected environment, if present
+    pub fn remove(&mut self, key: &str) {
+        let selected = self.selected.clone();
+        if self
+            .secrets_in_mut(selected.as_deref())
+            .remove(key)
+            .is_none()
+        {
+            match &selected {
+                Some(environment) if self.secrets.contains_key(key) => log::warn!(
+                    "Secret {} is not overridden in environment {}, doing nothing. Remove it without --env to remove the default.",
+                    key,
+                    environment
+                ),
+                _ => log::warn!("Asked to remove non-present secret {}, doing nothing", key),
+            }
+        }
+    }
+
+    /// Is this a default secret the selected environment doesn't override?
+    pub fn is_inherited(&self, key: &str) -> bool {
+        match self.selected.as_deref() {
+            Some(environment) => {
+                self.secrets.contains_key(key)
+                    && !self.secrets_in(Some(environment)).contains_key(key)
+            }
+            None => false,
+        }
     }
+
+    /// Are values bound to their secret's name, so that renaming needs the secret key?
+    ///
+    /// True since file format version 3.
+    pub fn binds_names(&self) -> bool {
+        self.file_id.is_some()
+    }
+
+    /// Rename a secret of the selected environment, keeping its value and metadata
+    ///
+    /// Since version 3, values are bound to the secret's name, so they are decrypted and sealed
+    /// again under the new name, which requires the secret key. Older files don't need it.
+    pub fn rename(&mut self, old: &str, new: String, secret_key: Option<&SecretKey>) -> Result<()> {
+        let selected = self.selected.clone();
+        let scope = selected.as_deref();
+        let secret = match (self.secrets_in(scope).get(old), scope) {
+            (Some(secret), _) => secret,
+            (None, Some(environment)) if self.secrets.contains_key(old) => bail!(
+                "Secret {old} is not overridden in environment {environment}. Rename it without --env to rename the default."
+            ),
+            (None, _) => bail!("Key does not exist: {old}"),
+        };
+        ensure!(
+            !self.secrets_in(scope).contains_key(&new),
+            "Secret {new} already exists"
+        );
+        let resealed = match self.file_id {
+            None => None,
+            Some(_) => {
+                let secret_key = secret_key.context(
+                    "Renaming a secret requires the secret key, as its value is bound to its name",
+                )?;
+                let plain = self.decrypt_secret(scope, old, secret, secret_key)?;
+                let ciphers = self.seal(scope, &self.bind(scope, &new, &plain))?;
+                let digest_key = self.digest_key(secret_key)?;
+                Some((
+                    ciphers,
+                    self.digest(scope, &new, &plain, digest_key.as_ref()),
+                ))
+            }
+        };
+        let mut secret = self
+            .secrets_in_mut(scope)
+            .remove(old)
+            .expect("Secret found above");
+        if let Some((ciphers, digest)) = resealed {
+            secret.ciphers = ciphers;
+            secret.digest = digest;
+        }
+        self.secrets_in_mut(scope).insert(new.clone(), secret);
+
+        match scope {
+            None => {
+                for (name, environment) in &self.environments {
+                    if environment.secrets.contains_key(old) {
+                        log::warn!("Environment {name} still overrides {old}, rename it there with --env {name}");
+                    }
+                }
+            }
+            Some(environment) => {
+                if self.secrets.contains_key(old) {
+                    log::warn!("Environment {environment} still falls back to the default {old}");
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Iterate over the recipients of the selected environment and their public keys, sorted by name
+    pub fn recipients(&self) -> impl Iterator<Item = (&String, &PublicKey)> {
+        self.members(self.selected.as_deref())
+            .into_iter()
+            .map(move |name| (name, &self.recipients[name]))
+    }
+
+    /// Ensure a new recipient's name and public key are not in use yet
+    fn check_new_recipient(&self, name: &str, public_key: &PublicKey) -> Result<()> {
+        validate_recipient(name)?;
+        ensure!(
+            !self.recipients.contains_key(name),
+            "Recipient {name} already exists"
+        );
+        if let Some((existing, _)) = self.recipients.iter().find(|(_, pk)| *pk == public_key) {
+            bail!("Public key already belongs to recipient {existing}");
+        }
+        Ok(())
+    }
+
+    /// Add a new recipient to the selected environment, re-encrypting every secret so they can
+    /// read it
+    ///
+    /// Returns the names of the re-encrypted secrets.
+    pub fn add_recipient(
+        &mut self,
+        name: String,
+        public_key: PublicKey,
+        secret_key: &SecretKey,
+    ) -> Result<Vec<String>> {
+        self.check_new_recipient(&name, &public_key)?;
+        let selected = self.selected.clone();
+        let plains = self.decrypt_all(selected.as_deref(), secret_key)?;
+        self.recipients.insert(name.clone(), public_key);
+        if let Some(environment) = &selected {
+            self.environments
+                .get_mut(environment)
+                .expect("Unknown environment")
+                .recipients
+                .insert(name);
+        }
+        self.reseal_all(plains)
+    }
+
+    /// Remove a recipient, re-encrypting every secret for the remaining ones
+    ///
+    /// Returns the names of the re-encrypted secrets.
+    pub fn remove_recipient(&mut self, name: &str, secret_key: &SecretKey) -> Result<Vec<String>> {
+        ensure!(
+            self.recipients.contains_key(name),
+            "No recipient named {name}"
+        );
+        let scope = self.environment_of(name).map(str::to_owned);
+        if let Some(environment) = &scope {
+            ensure!(
+                self.members(Some(environment)).len() > 1,
+                "Cannot remove {name}, it is the only recipient of environment {environment}. Remove the environment instead."
+            );
+        } else {
+            ensure!(
+                self.members(None).len() > 1,
+                "Cannot remove {name}, it is the only recipient"
+            );
+        }
+        let plains = self.decrypt_all(scope.as_deref(), secret_key)?;
+        self.recipients.remove(name);
+        if let Some(environment) = &scope {
+            self.environments
+                .get_mut(environment)
+                .expect("Unknown environment")
+                .recipients
+                .remove(name);
+        }
+        self.reseal_all(plains)
+    }
+
+    /// Replace a recipient's keypair with a freshly generated one, re-encrypting every secret
+    ///
+    /// If no recipient is given, the one owning the provided secret key is rotated.
+    /// Returns the new secret key and the names of the re-encrypted secrets.
+    pub fn rotate_key(
+        &mut self,
+        recipient: Option<&str>,
+        secret_key: &SecretKey,
+    ) -> Result<(SecretKey, Vec<String>)> {
+        let recipient = match recipient {
+            Some(recipient) => {
+                ensure!(
+                    self.recipients.contains_key(recipient),
+                    "No recipient named {recipient}"
+                );
+                recipient.to_owned()
+            }
+            None => self.recipient_for(secret_key)?.to_owned(),
+        };
+        let scope = self.environment_of(&recipient).map(str::to_owned);
+        let plains = self.decrypt_all(scope.as_deref(), secret_key)?;
+        let new_secret_key = SecretKey::generate(&mut OsRng);
+        self.recipients
+            .insert(recipient, new_secret_key.public_key());
+        let names = self.reseal_all(plains)?;
+        Ok((new_secret_key, names))
+    }
+
+    /// Iterate over the environments and the names of their recipients, sorted by name
+    pub fn environments(&self) -> impl Iterator<Item = (&String, &BTreeSet<String>)> {
+        self.environments
+            .iter()
+            .map(|(name, environment)| (name, &environment.recipients))
+    }
+
+    /// Add a new environment with a single recipient, re-encrypting the defaults for them
+    ///
+    /// Returns the names of the re-encrypted secrets.
+    pub fn add_environment(
+        &mut self,
+        name: String,
+        recipient: String,
+        public_key: PublicKey,
+        secret_key: &SecretKey,
+    ) -> Result<Vec<String>> {
+        validate_environment(&name)?;
+        ensure!(
+            !self.environments.contains_key(&name),
+            "Environment {name} already exists"
+        );
+        self.check_new_recipient(&recipient, &public_key)?;
+        let plains = self.decrypt_all(None, secret_key)?;
+        self.recipients.insert(recipient.clone(), public_key);
+        let mut recipients = BTreeSet::new();
+        recipients.insert(recipient);
+        self.environments.insert(
+            name,
+            Environment {
+                recipients,
+                secrets: HashMap::new(),
+            },
+        );
+        self.reseal_all(plains)
+    }
+
+    /// Remove an environment, its secrets and its recipients, re-encrypting the defaults without
+    /// them
+    ///
+    /// Returns the names of the re-encrypted secrets.
+    pub fn remove_environment(
+        &mut self,
+        name: &str,
+        secret_key: &SecretKey,
+    ) -> Result<Vec<String>> {
+        ensure!(
+            self.environments.contains_key(name),
+            "No environment named {name}"
+        );
+        // Decrypt first, the secret key may belong to the removed environment
+        let plains = self.decrypt_all(None, secret_key)?;
+        let environment = self
+            .environments
+            .remove(name)
+            .expect("Environment disappeared");
+        for recipient in environment.recipients {
+            self.recipients.remove(&recipient);
+        }
+        if self.selected.as_deref() == Some(name) {
+            self.selected = None;
+        }
+        self.reseal_all(plains)
+    }
+
+    /// Decrypt the defaults and the secrets of the given environment to their raw bytes
+    fn decrypt_all(&self, scope: Scope, secret_key: &SecretKey) -> Result<Plains> {
+        if let Some(environment) = scope {
+            ensure!(
+                self.can_decrypt(scope, secret_key),
+                "Re-encrypting the secrets of environment {environment} requires the secret key of one of its recipients"
+            );
+        }
+        let mut plains = Vec::new();
+        let scopes = if scope.is_some() {
+            vec![None, scope]
+        } else {
+            vec![None]
+        };
+        for scope in scopes {
+            for (key, secret) in self.secrets_in(scope) {
+                let plain = self.decrypt_secret(scope, key, secret, secret_key)?;
+                plains.push((scope.map(str::to_owned), key.clone(), plain));
+            }
+        }
+        plains.sort();
+        Ok(plains)
+    }
+
+    /// Replace secrets with a freshly sealed version of the given plaintext
+    ///
+    /// Since everything is re-encrypted, this also upgrades the file to the latest format.
+    /// The plaintexts must include every default secret, as the digest key is replaced.
+    fn reseal_all(&mut self, plains: Plains) -> Result<Vec<String>> {
+        if self.version < FILE_FORMAT_VERSION {
+            log::info!(
+                "Upgrading from file format version {} to {}",
+                self.version,
+                FILE_FORMAT_VERSION
+            );
+            self.version = FILE_FORMAT_VERSION;
+            self.file_id.get_or_insert_with(new_file_id);
+        }
+        // Recipients may have been removed, so never reuse the old digest key
+        let digest_key = new_digest_key();
+        self.digest_keys = self.seal(None, &digest_key)?;
+        let mut names = Vec::with_capacity(plains.len());
+        for (scope, key, plain) in plains {
+            let scope = scope.as_deref();
+            let ciphers = self.seal(scope, &self.bind(scope, &key, &plain))?;
+            let digest = self.digest(scope, &key, &plain, Some(&digest_key));
+            let secret = self
+                .secrets_in_mut(scope)
+                .get_mut(&key)
+                .expect("Secret disappeared while re-encrypting");
+            secret.ciphers = ciphers;
+            secret.digest = digest;
+            names.push(bound_name(scope, &key).into_owned());
+        }
+        Ok(names)
+    }
+
+    /// Upgrade the file to the latest format
+    ///
+    /// With a secret key, every secret is re-encrypted, which always reaches the latest version.
+    /// Without one, only upgrades that don't require re-encryption are applied.
+    /// Returns the names of the re-encrypted secrets.
+    pub fn migrate(&mut self, secret_key: Option<&SecretKey>) -> Result<Vec<String>> {
+        match secret_key {
+            Some(secret_key) => {
+                // Environments need version 4 and their secrets have no digests, which leaves
+                // only the default secrets to re-encrypt
+                let plains = self.decrypt_all(None, secret_key)?;
+                self.reseal_all(plains)
+            }
+            None => {
+                while let Some(change) = self.pending_changes().next().filter(|c| !c.reencrypt) {
+                    log::info!(
+                        "Upgrading from file format version {} to {}",
+                        self.version,
+                        change.version
+                    );
+                    self.version = change.version;
+                }
+                Ok(vec![])
+            }
+        }
+    }
+
+    /// Format changes this file is missing compared to the latest version
+    pub fn pending_changes(&self) -> impl Iterator<Item = &'static FormatChange> {
+        let version = self.version;
+        FORMAT_CHANGES
+            .iter()
+            .filter(move |change| change.version > version)
+    }
+
+    /// Is this file using the latest file format version?
+    pub fn is_latest_version(&self) -> bool {
+        self.version == FILE_FORMAT_VERSION
+    }
+
+    /// The latest file format version, used for newly created files
+    pub fn latest_version() -> u32 {
+        FILE_FORMAT_VERSION
+    }
+
+    /// File format version of this file
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    /// Get the secret key from the given source
+    ///
+    /// Validates that it belongs to one of the recipients of the selected environment
+    pub fn load_secret_key(&self, source: &KeySource) -> Result<SecretKey> {
+        (|| {
+            let scope = self.selected.as_deref();
+            let audience = self
+                .recipients
+                .iter()
+                .filter(|(name, _)| self.in_scope(scope, name))
+                .map(|(name, public_key)| (name.clone(), public_key.clone()))
+                .collect();
+            let secret = source.load(&audience)?;
+            let recipient = self.recipient_for(&secret)?;
+            if let Some(environment) = scope {
+                ensure!(
+                    self.in_scope(scope, recipient),
+                    "Secret key of recipient {recipient} does not belong to environment {environment}"
+                );
+            }
+            log::debug!("Loaded secret key for recipient {}", recipient);
+            Ok(secret)
+        })()
+        .context("Error loading secret key")
+    }
+
+    /// Load the secret key if one was provided and the file format benefits from it
+    ///
+    /// Used for optional features like detecting unchanged values. Problems loading the key
+    /// are logged instead of failing.
+    pub fn try_load_secret_key(&self, source: &KeySource) -> Option<SecretKey> {
+        if self.version < 4 || !source.is_provided() {
+            return None;
+        }
+        self.load_secret_key(source)
+            .map_err(|e| log::warn!("Continuing without secret key: {e:#}"))
+            .ok()
+    }
+
+    /// Find the name of the recipient the given secret key belongs to
+    fn recipient_for(&self, secret_key: &SecretKey) -> Result<&str> {
+        let public_key = secret_key.public_key();
+        self.recipients
+            .iter()
+            .find(|(_, recipient)| **recipient == public_key)
+            .map(|(name, _)| name.as_str())
+            .context("Secret key does not match any of the config file's recipients")
+    }
+
+    /// Iterate over the secrets of the selected environment as text
+    ///
+    /// Binary values are base64 encoded, see [Config::value_kind].
+    pub fn iter_secrets<'a>(
+        &'a self,
+        secret_key: &'a SecretKey,
+    ) -> impl Iterator<Item = Result<(&'a String, String)>> {
+        self.visible_secrets()
+            .into_iter()
+            .map(move |(scope, key, secret)| {
+                let plain = self.decrypt_secret(scope, key, secret, secret_key)?;
+                let value = match secret.kind {
+                    ValueKind::Text => String::from_utf8(plain).expect("Text values are UTF-8"),
+                    ValueKind::Binary => {
+                        log::info!(
+                            "Secret {key} holds a binary value, providing it base64 encoded"
+                        );
+                        base64::engine::general_purpose::STANDARD.encode(plain)
+                    }
+                };
+                Ok((key, value))
+            })
+    }
+
+    /// Look up a specific secret value, returning its raw bytes
+    pub(crate) fn get_secret(&self, key: &str, secret_key: &SecretKey) -> Result<Vec<u8>> {
+        self.lookup(key)
+            .with_context(|| format!("Key does not exist: {key}"))
+            .and_then(|(scope, secret)| self.decrypt_secret(scope, key, secret, secret_key))
+    }
+
+    /// Does a secret visible in the selected environment hold the same value in another
+    /// version of the file?
+    ///
+    /// Tells from the ciphertexts and digests, without a secret key. None if it can't tell,
+    /// e.g. because the value was encrypted again, or either version lacks the secret.
+    pub fn same_value(&self, other: &Config, key: &str) -> Option<bool> {
+        let (scope, secret) = self.lookup(key)?;
+        let (other_scope, other_secret) = other.lookup(key)?;
+        if scope != other_scope || secret.kind != other_secret.kind {
+            return None;
+        }
+        if secret.ciphers == other_secret.ciphers {
+            return Some(true);
+        }
+        let (digest, other_digest) = (secret.digest?, other_secret.digest?);
+        match (self.version, other.version) {
+            // Plain SHA-256 digests
+            (..=3, ..=3) => Some(digest == other_digest),
+            // Keyed digests are comparable if the digest key is the same, as shown by an
+            // unchanged sealed copy of it. Equal digests of different keys are unlikely.
+            (4.., 4..) => {
+                let same_key = self
+                    .digest_keys
+                    .iter()
+                    .any(|(name, sealed)| other.digest_keys.get(name) == Some(sealed));
+                (same_key || digest == other_digest).then_some(digest == other_digest)
+            }
+            _ => None,
+        }
+    }
+
+    /// What kind of value the given secret holds, if it exists
+    pub fn value_kind(&self, key: &str) -> Option<ValueKind> {
+        self.lookup(key).map(|(_, secret)| secret.kind)
+    }
+
+    /// Render the recipients and every secret of the file as sorted lines, for reviewing changes
+    ///
+    /// Secrets are shown as `NAME=value`, prefixed with the environment for overrides like
+    /// `prod/NAME`. Values the secret key can't decrypt, or all of them without a key, are
+    /// shown as a fingerprint of their ciphertexts, which changes with every encryption.
+    pub fn render(&self, secret_key: Option<&SecretKey>, style: ValueStyle) -> Result<Vec<String>> {
+        let names = |scope| -> Vec<&str> {
+            self.members(scope)
+                .into_iter()
+                .map(String::as_str)
+                .collect()
+        };
+        let mut lines = vec![format!("# recipients: {}", names(None).join(", "))];
+        for environment in self.environments.keys() {
+            let members = names(Some(environment));
+            lines.push(format!(
+                "# environment {environment}: {}",
+                members.join(", ")
+            ));
+        }
+        let hash_key = secret_key.map(|secret_key| {
+            let mut hasher = Sha256::new();
+            hasher.update(b"amber render\0");
+            hasher.update(secret_key.to_bytes());
+            hasher.finalize()
+        });
+        let scopes =
+            std::iter::once(None).chain(self.environments.keys().map(|env| Some(env.as_str())));
+        for scope in scopes {
+            let mut secrets: Vec<_> = self.secrets_in(scope).iter().collect();
+            secrets.sort_unstable_by_key(|(key, _)| *key);
+            for (key, secret) in secrets {
+                let secret_key = secret_key.filter(|secret_key| {
+                    style != ValueStyle::Masked && self.can_decrypt(scope, secret_key)
+                });
+                let value = match (secret_key, hash_key) {
+                    (Some(secret_key), Some(hash_key)) => {
+                        let plain = self.decrypt_secret(scope, key, secret, secret_key)?;
+                        match (style, secret.kind) {
+                            (ValueStyle::Hashed, _) => {
+                                let mut mac = Hmac::<Sha256>::new_from_slice(&hash_key)
+                                    .expect("HMAC accepts any key length");
+                                mac.update(&plain);
+                                let hash = mac.finalize().into_bytes();
+                                format!("hmac:{}", hex::encode(&hash[..8]))
+                            }
+                            (_, ValueKind::Text) => escape_line(
+                                std::str::from_utf8(&plain).expect("Text values are UTF-8"),
+                            ),
+                            (_, ValueKind::Binary) => format!(
+                                "base64:{}",
+                                base64::engine::general_purpose::STANDARD.encode(plain)
+                            ),
+                        }
+                    }
+                    _ => {
+                        let mut hasher = Sha256::new();
+                        for cipher in secret.ciphers.values() {
+                            hasher.update(cipher);
+                        }
+                        format!("encrypted:{}", hex::encode(&hasher.finalize()[..8]))
+                    }
+                };
+                lines.push(format!("{}={value}", bound_name(scope, key)));
+            }
+        }
+        Ok(lines)
+    }
+
+    /// Decrypt a secret and verify its digest, if present
+    ///
+    /// Text values are also checked to be valid UTF-8.
+    fn decrypt_secret(
+        &self,
+        scope: Scope,
+        key: &str,
+        secret: &Secret,
+        secret_key: &SecretKey,
+    ) -> Result<Vec<u8>> {
+        (|| {
+            let recipient = self.recipient_for(secret_key)?;
+            let plain = secret.decrypt(
+                secret_key,
+                recipient,
+                &bound_name(scope, key),
+                self.file_id.as_ref(),
+            )?;
+            if let Some(expected) = secret.digest {
+                let digest_key = self.digest_key(secret_key)?;
+                let digest = self
+                    .digest(scope, key, &plain, digest_key.as_ref())
+                    .context("Unable to compute digest")?;
+                ensure!(
+                    digest == expected,
+                    "Hash mismatch, expected {}, received {}",
+                    hex::encode(expected),
+                    hex::encode(digest)
+                );
+            }
+            if secret.kind == ValueKind::Text {
+                std::str::from_utf8(&plain).context("Invalid UTF-8 encoding")?;
+            }
+            Ok(plain)
+        })()
+        .with_context(|| format!("Error while decrypting secret named {key}"))
+    }
+}
+
+impl Secret {
+    fn from_raw(raw: SecretRaw, version: u32) -> Result<(String, Self)> {
+        let parse_digest = |digest: &str| -> Result<[u8; 32]> {
+            hex::decode(digest)
+                .ok()
+                .context("Non-hex digest")?
+                .try_into()
+                .map_err(|_| anyhow!("Error parsing into digest"))
+        };
+        for tag in &raw.tags {
+            validate_tag(tag).with_context(|| format!("Invalid tag on secret {}", raw.name))?;
+        }
+        let digest = if version < 4 {
+            ensure!(
+                raw.digest.is_none(),
+                "Only version 4 and later secrets contain a keyed digest"
+            );
+            let sha256 = raw
+                .sha256
+                .as_deref()
+                .with_context(|| format!("No sha256 found for secret {}", raw.name))?;
+            Some(parse_digest(sha256)?)
+        } else {
+            ensure!(
+                raw.sha256.is_none(),
+                "Version 4 and later secrets use a keyed digest instead of sha256"
+            );
+            raw.digest.as_deref().map(parse_digest).transpose()?
+        };
+        let ciphers = if version == 1 {
+            ensure!(
+                raw.ciphers.is_empty(),
+                "Version 1 secrets cannot contain per-recipient ciphers"
+            );
+            let name = &raw.name;
+            let cipher = raw
+                .cipher
+                .with_context(|| format!("No cipher found for secret {name}"))?;
+            let mut ciphers = BTreeMap::new();
+            ciphers.insert(DEFAULT_RECIPIENT.to_owned(), cipher);
+            ciphers
+        } else {
+            ensure!(
+                raw.cipher.is_none(),
+                "Only version 1 secrets contain a single cipher, use ciphers instead"
+            );
+            raw.ciphers
+        };
+        let ciphers = ciphers
+            .into_iter()
+            .map(|(recipient, cipher)| {
+                let cipher = hex::decode(cipher).ok().context("Non-hex ciphertext")?;
+                Ok((recipient, cipher))
+            })
+            .collect::<Result<_>>()?;
+        let metadata = Metadata {
+            description: raw.description,
+            owner: raw.owner,
+            created_at: raw.created_at,
+            updated_at: raw.updated_at,
+            tags: raw.tags,
+            expires_at: raw.expires_at,
+            rotate_after: raw.rotate_after,
+        };
+        ensure!(
+            version >= 5 || metadata.is_empty(),
+            "Only version 5 and later files contain secret metadata, found some on {}",
+            raw.name
+        );
+        ensure!(
+            version >= 5 || raw.kind == ValueKind::Text,
+            "Only version 5 and later files contain binary values, found one in {}",
+            raw.name
+        );
+        Ok((
+            raw.name,
+            Secret {
+                digest,
+                ciphers,
+                kind: raw.kind,
+                metadata,
+            },
+        ))
+    }
+
+    fn to_raw(&self, name: &str, version: u32) -> SecretRaw {
+        let mut ciphers: BTreeMap<String, String> = self
+            .ciphers
+            .iter()
+            .map(|(recipient, cipher)| (recipient.clone(), hex::encode(cipher)))
+            .collect();
+        let cipher = if version == 1 {
+            let cipher = std::mem::take(&mut ciphers);
+            cipher.into_values().next()
+        } else {
+            None
+        };
+        let (sha256, digest) = if version < 4 {
+            (self.digest.map(hex::encode), None)
+        } else {
+            (None, self.digest.map(hex::encode))
+        };
+        let metadata = self.metadata.clone();
+        SecretRaw {
+            name: name.to_owned(),
+            kind: self.kind,
+            description: metadata.description,
+            owner: metadata.owner,
+            created_at: metadata.created_at,
+            updated_at: metadata.updated_at,
+            tags: metadata.tags,
+            expires_at: metadata.expires_at,
+            rotate_after: metadata.rotate_after,
+            sha256,
+            digest,
+            cipher,
+            ciphers,
+        }
+    }
+
+    /// Decrypt this secret, returning the raw value
+    ///
+    /// If the file binds ciphertexts, verifies that this one belongs to the given file and key.
+    fn decrypt(
+        &self,
+        secret_key: &SecretKey,
+        recipient: &str,
+        key: &str,
+        file_id: Option<&FileId>,
+    ) -> Result<Vec<u8>> {
+        let cipher = self
+            .ciphers
+            .get(recipient)
+            .with_context(|| format!("Not encrypted for recipient {recipient}"))?;
+        let plain = secret_key
+            .unseal(&cipher[..])
+            .map_err(|_| anyhow!("Unable to decrypt secret"))?;
+        match file_id {
+            None => Ok(plain),
+            Some(file_id) => unbind(&plain, file_id, key).map(<[u8]>::to_vec),
+        }
+    }
+}
+
+/// Parse recipients into the maps of every recipient's public and digest key
+///
+/// Returns the names of the parsed recipients.
+fn parse_recipients(
+    version: u32,
+    raws: Vec<RecipientRaw>,
+    recipients: &mut BTreeMap<String, PublicKey>,
+    digest_keys: &mut BTreeMap<String, Vec<u8>>,
+) -> Result<BTreeSet<String>> {
+    let mut names = BTreeSet::new();
+    for recipient in raws {
+        validate_recipient(&recipient.name)?;
+        match &recipient.digest_key {
+            Some(digest_key) => {
+                ensure!(
+                    version >= 4,
+                    "Only version 4 and later files contain digest keys"
+                );
+                let digest_key = hex::decode(digest_key).ok().context("Non-hex digest key")?;
+                digest_keys.insert(recipient.name.clone(), digest_key);
+            }
+            None => ensure!(
+                version < 4,
+                "No digest key found for recipient {}",
+                recipient.name
+            ),
+        }
+        let public_key = parse_public_key(&recipient.public_key)
+            .with_context(|| format!("Invalid recipient {}", recipient.name))?;
+        ensure!(
+            recipients
+                .insert(recipient.name.clone(), public_key)
+                .is_none(),
+            "Duplicated recipient: {}",
+            recipient.name
+        );
+        names.insert(recipient.name);
+    }
+    Ok(names)
+}
+
+/// Parse secrets, checking they are encrypted for exactly the given recipients
+fn parse_secrets(
+    raws: Vec<SecretRaw>,
+    version: u32,
+    recipients: &BTreeSet<String>,
+    in_environment: bool,
+) -> Result<HashMap<String, Secret>> {
+    let mut secrets = HashMap::new();
+    for raw in raws {
+        let (key, secret) = Secret::from_raw(raw, version)?;
+        for recipient in recipients {
+            ensure!(
+                secret.ciphers.contains_key(recipient),
+                "Secret {} is not encrypted for recipient {}",
+                key,
+                recipient
+            );
+        }
+        ensure!(
+            secret.ciphers.len() == recipients.len(),
+            "Secret {} is encrypted for an unknown recipient",
+            key
+        );
+        ensure!(
+            !in_environment || secret.digest.is_none(),
+            "Secret {} of an environment contains a digest",
+            key
+        );
+        ensure!(
+            !secrets.contains_key(&key),
+            "Duplicated secret key: {}",
+            key
+        );
+        let old = secrets.insert(key, secret);
+        assert!(old.is_none());
+    }
+    Ok(secrets)
+}
+
+/// Write a file atomically, creating its parent directory if needed
+fn write_atomic(path: &Path, contents: &[u8]) -> Result<()> {
+    let parent = path.parent().context("File must have a parent directory")?;
+    // An empty parent refers to the current directory
+    let parent = if parent.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        parent
+    };
+    fs_err::create_dir_all(parent).context("Unable to create parent directory")?;
+    let existing = fs_err::metadata(path)
+        .ok()
+        .map(|metadata| metadata.permissions());
+    let mut builder = tempfile::Builder::new();
+    // Temporary files are private by default, give new files the permissions allowed by the
+    // umask instead, like File::create does
+    #[cfg(unix)]
+    if existing.is_none() {
+        use std::os::unix::fs::PermissionsExt;
+        builder.permissions(std::fs::Permissions::from_mode(0o666));
+    }
+    let mut file = builder
+        .tempfile_in(parent)
+        .context("Unable to create temporary file")?;
+    std::io::Write::write_all(&mut file, contents)?;
+    if let Some(permissions) = existing {
+        file.as_file().set_permissions(permissions)?;
+    }
+    file.as_file().sync_all()?;
+    file.persist(path)?;
+    Ok(())
+}
+
+/// Like [write_atomic], but leaves the file alone if its contents are already the same
+fn write_if_changed(path: &Path, contents: &[u8]) -> Result<()> {
+    if fs_err::read(path).ok().as_deref() == Some(contents) {
+        return Ok(());
+    }
+    write_atomic(path, contents)
+}
+
+/// Read a config stored in the [Layout::Directory] layout
+fn read_directory(files: &dyn Files, dir: &Path) -> Result<ConfigRaw> {
+    let manifest = dir.join(MANIFEST);
+    let mut raw: ConfigRaw = serde_yaml::from_slice(&files.read(&manifest)?)
+        .with_context(|| format!("Invalid manifest {}", manifest.display()))?;
+    ensure!(
+        raw.secrets.is_empty() && raw.environments.iter().all(|env| env.secrets.is_empty()),
+        "The manifest {} cannot contain secrets, each secret has its own file",
+        manifest.display()
+    );
+    raw.secrets = read_secrets(files, &dir.join(SECRETS_DIR))?;
+    let environments_dir = dir.join(ENVIRONMENTS_DIR);
+    for environment in &mut raw.environments {
+        validate_environment(&environment.name)?;
+        environment.secrets = read_secrets(files, &environments_dir.join(&environment.name))?;
+    }
+    for (name, _) in files.list(&environments_dir)? {
+        ensure!(
+            raw.environments.iter().any(|env| env.name == name),
+            "{} does not belong to any environment in {}",
+            environments_dir.join(name).display(),
+            manifest.display()
+        );
+    }
+    Ok(raw)
+}
+
+/// Read every secret file of a directory, which may not exist
+fn read_secrets(files: &dyn Files, dir: &Path) -> Result<Vec<SecretRaw>> {
+    let mut secrets = Vec::new();
+    for (name, is_dir) in files.list(dir)? {
+        let path = dir.join(name);
+        if is_dir || !is_secret_file(&path) {
+            continue;
+        }
+        let secret: Result<SecretRaw> = (|| {
+            let secret: SecretRaw = serde_yaml::from_slice(&files.read(&path)?)?;
+            ensure!(
+                path.file_stem() == Some(OsStr::new(&secret.name)),
+                "The file contains secret {}, and should be named {}.yaml",
+                secret.name,
+                secret.name
+            );
+            Ok(secret)
+        })();
+        secrets.push(secret.with_context(|| format!("Invalid secret file {}", path.display()))?);
+    }
+    Ok(secrets)
+}
+
+/// Write a config in the [Layout::Directory] layout, removing files of deleted secrets
+fn write_directory(dir: &Path, mut raw: ConfigRaw) -> Result<()> {
+    write_secrets(&dir.join(SECRETS_DIR), std::mem::take(&mut raw.secrets))?;
+    let environments_dir = dir.join(ENVIRONMENTS_DIR);
+    for environment in &mut raw.environments {
+        let secrets = std::mem::take(&mut environment.secrets);
+        write_secrets(&environments_dir.join(&environment.name), secrets)?;
+    }
+    if environments_dir.is_dir() {
+        for entry in fs_err::read_dir(&environments_dir)? {
+            let entry = entry?;
+            let name = entry.file_name();
+            if !raw
+                .environments
+                .iter()
+                .any(|env| name.to_str() == Some(&env.name))
+            {
+                fs_err::remove_dir_all(entry.path())?;
+            }
+        }
+    }
+    write_if_changed(&dir.join(MANIFEST), manifest_to_string(&raw)?.as_bytes())
+}
+
+/// Serialize the manifest of the directory layout, from a config without secrets
+fn manifest_to_string(raw: &ConfigRaw) -> Result<String> {
+    // Leave out the empty list of secrets, to make clear they don't belong here
+    let mut manifest = serde_yaml::to_value(raw)?;
+    if let serde_yaml::Value::Mapping(mapping) = &mut manifest {
+        mapping.remove("secrets");
+    }
+    Ok(serde_yaml::to_string(&manifest)?)
+}
+
+/// Write one file per secret into a directory, removing those of other secrets
+fn write_secrets(dir: &Path, secrets: Vec<SecretRaw>) -> Result<()> {
+    let mut names = HashSet::new();
+    let mut folded = HashSet::new();
+    for secret in secrets {
+        validate_file_name(&secret.name)?;
+        ensure!(
+            folded.insert(secret.name.to_lowercase()),
+            "Secret {} only differs by case from another one, which the directory layout doesn't support",
+            secret.name
+        );
+        let path = dir.join(format!("{}.yaml", secret.name));
+        write_if_changed(&path, serde_yaml::to_string(&secret)?.as_bytes())?;
+        names.insert(secret.name);
+    }
+    if dir.is_dir() {
+        for entry in fs_err::read_dir(dir)? {
+            let path = entry?.path();
+            let name = path.file_stem().and_then(OsStr::to_str);
+            let stale = path.is_file() && !name.map_or(false, |name| names.contains(name));
+            if is_secret_file(&path) && stale {
+                fs_err::remove_file(path)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn is_secret_file(path: &Path) -> bool {
+    path.extension() == Some(OsStr::new("yaml"))
+}
+
+/// Secret names become file names in the directory layout
+fn validate_file_name(name: &str) -> Result<()> {
+    ensure!(
+        !name.is_empty()
+            && !name.starts_with('.')
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "_-.".contains(c)),
+        "Secret name {name:?} cannot be used as a file name in the directory layout, only letters, digits, '_', '-' and '.' are allowed"
+    );
+    Ok(())
+}
+
+/// Delete a config file, or the files of a config in the directory layout
+///
+/// Other files in the directory are left alone, along with the directory itself if any remain.
+pub fn delete(path: &Path) -> Result<()> {
+    if !path.is_dir() {
+        return Ok(fs_err::remove_file(path)?);
+    }
+    fs_err::remove_file(path.join(MANIFEST))?;
+    for dir in [SECRETS_DIR, ENVIRONMENTS_DIR] {
+        let dir = path.join(dir);
+        if dir.exists() {
+            fs_err::remove_dir_all(dir)?;
+        }
+    }
+    if fs_err::read_dir(path)?.next().is_none() {
+        fs_err::remove_dir(path)?;
+    } else {
+        log::warn!("Keeping {} as it contains other files", path.display());
+    }
+    Ok(())
+}
+
+/// Three-way merge of amber files, as a git merge driver
+///
+/// Secrets are merged individually: a secret added, changed or removed on one side only takes
+/// that side's version. Everything else, like the recipients, is merged as a whole. The result
+/// is written to `ours`, and the conflicts are returned.
+///
+/// Conflicting secrets keep our version. If anything else conflicts, or keeping our version
+/// gives an invalid file, `ours` is left untouched.
+pub fn merge(base: &Path, ours: &Path, theirs: &Path) -> Result<Vec<String>> {
+    let read = |path: &Path| -> Result<Option<(bool, ConfigRaw)>> {
+        let res: Result<Option<(bool, ConfigRaw)>> = (|| {
+            let contents = fs_err::read_to_string(path)?;
+            // The base is empty if both sides added the file
+            if contents.trim().is_empty() {
+                return Ok(None);
+            }
+            let value: serde_yaml::Value = serde_yaml::from_str(&contents)?;
+            // Manifests of the directory layout have no secrets key
+            let is_manifest = value.get("secrets").is_none();
+            Ok(Some((is_manifest, serde_yaml::from_value(value)?)))
+        })();
+        res.with_context(|| format!("Unable to read file {}", path.display()))
+    };
+    let base = read(base)?.map(|(_, raw)| split_raw(raw));
+    let (is_manifest, ours_raw) = read(ours)?.context("Our version of the file is empty")?;
+    let ours_raw = split_raw(ours_raw);
+    let (_, theirs_raw) = read(theirs)?.context("Their version of the file is empty")?;
+    let theirs_raw = split_raw(theirs_raw);
+
+    let base_header = base.as_ref().map(|(header, _)| header);
+    let header = merge3(
+        base_header,
+        Some(&ours_raw.0),
+        Some(&theirs_raw.0),
+        ConfigRaw::eq,
+    );
+    let Some(mut merged) = header.ok().flatten() else {
+        return Ok(vec![
+            "the recipients or file settings changed on both sides".to_owned(),
+        ]);
+    };
+
+    // Secrets are sealed for the recipients and digest keys of their side's header. Those of a
+    // side whose header differs from the merged one can't be taken over as they are.
+    let sealing = |header: &ConfigRaw| ConfigRaw {
+        extends: None,
+        ..header.clone()
+    };
+    let merged_sealing = sealing(&merged);
+    let ours_sealed = sealing(&ours_raw.0) == merged_sealing;
+    let theirs_sealed = sealing(&theirs_raw.0) == merged_sealing;
+
+    let mut conflicts = Vec::new();
+    let empty = BTreeMap::new();
+    let base_secrets = base.as_ref().map_or(&empty, |(_, secrets)| secrets);
+    let (ours_secrets, theirs_secrets) = (&ours_raw.1, &theirs_raw.1);
+    let names: BTreeSet<_> = base_secrets
+        .keys()
+        .chain(ours_secrets.keys())
+        .chain(theirs_secrets.keys())
+        .collect();
+    for name in names {
+        let (scope, key) = name;
+        let ours = ours_secrets.get(name);
+        let theirs = theirs_secrets.get(name);
+        let secret = match merge3(base_secrets.get(name), ours, theirs, same_secret) {
+            std::result::Result::Ok(Some(secret))
+                if !(ours_sealed && ours == Some(&secret)
+                    || theirs_sealed && theirs == Some(&secret)) =>
+            {
+                conflicts.push(format!(
+                    "secret {} is encrypted for recipients other than those of the merged file",
+                    bound_name(scope.as_deref(), key)
+                ));
+                ours.cloned()
+            }
+            std::result::Result::Ok(secret) => secret,
+            Err(()) => {
+                conflicts.push(format!(
+                    "secret {} changed on both sides",
+                    bound_name(scope.as_deref(), key)
+                ));
+                ours.cloned()
+            }
+        };
+        let Some(secret) = secret else { continue };
+        match scope {
+            None => merged.secrets.push(secret),
+            Some(scope) => match merged
+                .environments
+                .iter_mut()
+                .find(|env| &env.name == scope)
+            {
+                Some(environment) => environment.secrets.push(secret),
+                None => conflicts.push(format!(
+                    "secret {} belongs to a removed environment",
+                    bound_name(Some(scope), key)
+                )),
+            },
+        }
+    }
+
+    if let Err(e) = Config::from_raw(merged.clone()) {
+        if conflicts.is_empty() {
+            return Err(e.context("Invalid merge result"));
+        }
+        conflicts.push(format!(
+            "keeping our version of the secrets gives an invalid file: {e}"
+        ));
+        return Ok(conflicts);
+    }
+    let contents = if is_manifest {
+        manifest_to_string(&merged)?
+    } else {
+        serde_yaml::to_string(&merged)?
+    };
+    write_atomic(ours, contents.as_bytes())
+        .with_context(|| format!("Unable to write file {}", ours.display()))?;
+    Ok(conflicts)
+}
+
+/// The secrets of a raw config, keyed by environment and name
+type RawSecrets = BTreeMap<(Option<String>, String), SecretRaw>;
+
+/// Take the secrets out of a raw config, for [merge]
+fn split_raw(mut raw: ConfigRaw) -> (ConfigRaw, RawSecrets) {
+    let mut secrets = BTreeMap::new();
+    for secret in std::mem::take(&mut raw.secrets) {
+        secrets.insert((None, secret.name.clone()), secret);
+    }
+    for environment in &mut raw.environments {
+        for secret in std::mem::take(&mut environment.secrets) {
+            secrets.insert(
+                (Some(environment.name.clone()), secret.name.clone()),
+                secret,
+            );
+        }
+    }
+    (raw, secrets)
+}
+
+/// Whether two versions of a secret hold the same value, e.g. set on both sides of a merge
+///
+/// Ciphertexts and timestamps differ each time a value is encrypted, so compare digests, or the
+/// plain SHA-256 before version 4. Secrets of environments have neither, so only identical
+/// ciphertexts compare equal.
+fn same_secret(x: &SecretRaw, y: &SecretRaw) -> bool {
+    let comparable = |secret: &SecretRaw| SecretRaw {
+        cipher: None,
+        ciphers: BTreeMap::new(),
+        created_at: None,
+        updated_at: None,
+        ..secret.clone()
+    };
+    let has_digest = |secret: &SecretRaw| secret.digest.is_some() || secret.sha256.is_some();
+    x == y || (has_digest(x) && comparable(x) == comparable(y))
+}
+
+/// Three-way merge of a single item, None standing for a missing one
+///
+/// Fails if both sides changed the item differently.
+fn merge3<T: Clone>(
+    base: Option<&T>,
+    ours: Option<&T>,
+    theirs: Option<&T>,
+    eq: impl Fn(&T, &T) -> bool,
+) -> std::result::Result<Option<T>, ()> {
+    let same = |x: Option<&T>, y: Option<&T>| match (x, y) {
+        (Some(x), Some(y)) => eq(x, y),
+        (x, y) => x.is_none() && y.is_none(),
+    };
+    if same(ours, theirs) || same(theirs, base) {
+        std::result::Result::Ok(ours.cloned())
+    } else if same(ours, base) {
+        std::result::Result::Ok(theirs.cloned())
+    } else {
+        Err(())
+    }
+}
+
+fn secrets_to_raw(secrets: &HashMap<String, Secret>, version: u32) -> Vec<SecretRaw> {
+    let mut secrets: Vec<SecretRaw> = secrets
+        .iter()
+        .map(|(key, value)| value.to_raw(key, version))
+        .collect();
+    secrets.sort_unstable_by(|x, y| x.name.cmp(&y.name));
+    secrets
+}
+
+/// Keep a value on a single line, escaping backslashes and control characters like newlines
+fn escape_line(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if c == '\\' || c.is_control() {
+            escaped.extend(c.escape_default());
+        } else {
+            escaped.push(c);
+        }
+    }
+    escaped
+}
+
+/// Name bound to a secret's ciphertexts, prefixed with the environment it belongs to
+///
+/// Keeps ciphertexts of the defaults from passing as an environment's, and vice versa.
+fn bound_name<'a>(scope: Scope, key: &'a str) -> Cow<'a, str> {
+    match scope {
+        None => Cow::Borrowed(key),
+        Some(environment) => Cow::Owned(format!("{environment}/{key}")),
+    }
+}
+
+/// Strip and verify the binding added by [Config::bind]
+fn unbind<'a>(plain: &'a [u8], file_id: &FileId, key: &str) -> Result<&'a [u8]> {
+    let tampered = "the file may have been tampered with";
+    ensure!(
+        plain.len() >= file_id.len() + 4,
+        "Ciphertext is too short, {tampered}"
+    );
+    let (found_file_id, rest) = plain.split_at(file_id.len());
+    ensure!(
+        found_file_id == file_id,
+        "Ciphertext belongs to a different amber file, {tampered}"
+    );
+    let (len, rest) = rest.split_at(4);
+    let len = u32::from_be_bytes(len.try_into().expect("Length is 4 bytes")) as usize;
+    ensure!(rest.len() >= len, "Ciphertext is truncated, {tampered}");
+    let (name, value) = rest.split_at(len);
+    ensure!(
+        name == key.as_bytes(),
+        "Ciphertext belongs to secret {}, not {key}, {tampered}",
+        String::from_utf8_lossy(name)
+    );
+    Ok(value)
+}
+
+fn new_digest_key() -> DigestKey {
+    use crypto_box::aead::rand_core::RngCore;
+    let mut digest_key = DigestKey::default();
+    OsRng.fill_bytes(&mut digest_key);
+    digest_key
+}
+
+fn new_file_id() -> FileId {
+    use crypto_box::aead::rand_core::RngCore;
+    let mut file_id = FileId::default();
+    OsRng.fill_bytes(&mut file_id);
+    file_id
+}
+
+pub(crate) fn parse_public_key(hex: &str) -> Result<PublicKey> {
+    let public_key: [u8; 32] = hex::decode(hex)
+        .ok()
+        .context("Public key is not hex")?
+        .try_into()
+        .map_err(|_| anyhow!("Invalid Public key"))?;
+    Ok(PublicKey::from(public_key))
+}
+
+/// Recipient names are free form, but must be non-empty and without whitespace
+fn validate_recipient(name: &str) -> Result<()> {
+    ensure!(!name.is_empty(), "Cannot provide an empty recipient name");
+    ensure!(
+        !name.chars().any(|c| c.is_whitespace() || c.is_control()),
+        "Recipient name must not contain whitespace: {name:?}"
+    );
+    Ok(())
+}
+
+/// Environment names are made of lower case ASCII letters, digits, dashes and underscores
+fn validate_environment(name: &str) -> Result<()> {
+    ensure!(!name.is_empty(), "Cannot provide an empty environment name");
+    ensure!(
+        name.chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_'),
+        "Environment name must be exclusively lower case ASCII, digits, dashes and underscores: {name:?}"
+    );
+    Ok(())
+}
+
+/// Tags are free form, but must be non-empty and without whitespace or commas
+pub fn validate_tag(tag: &str) -> Result<()> {
+    ensure!(!tag.is_empty(), "Cannot provide an empty tag");
+    ensure!(
+        !tag.chars()
+            .any(|c| c.is_whitespace() || c.is_control() || c == ','),
+        "Tag must not contain whitespace or commas: {tag:?}"
+    );
+    Ok(())
 }
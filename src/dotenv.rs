@@ -0,0 +1,127 @@
+//! Reading and writing `.env` style files of `NAME=value` lines.
+//!
+//! Values may be unquoted, single quoted (taken literally) or double quoted (with `\n`, `\r`,
+//! `\t`, `\"` and `\\` escapes). Quoted values may span several lines. Variables like `$HOME`
+//! are not expanded.
+
+use anyhow::*;
+
+/// A name and its value
+type Pair = (String, String);
+
+/// Format a name and value as a line, quoting the value if needed
+pub fn format_line(name: &str, value: &str) -> String {
+    let plain = value.trim() == value
+        && !value
+            .chars()
+            .any(|c| c.is_control() || matches!(c, '"' | '\'' | '\\' | '#'));
+    if plain {
+        return format!("{name}={value}");
+    }
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    format!("{name}={quoted}")
+}
+
+/// Parse the `NAME=value` pairs, in order of appearance
+///
+/// Blank lines and lines starting with `#` are skipped, as is an `export` prefix. Unquoted
+/// values end at the end of the line or at a ` #` comment, and are trimmed.
+pub fn parse(content: &str) -> Result<Vec<Pair>> {
+    let mut pairs = Vec::new();
+    let mut rest = content;
+    let mut line_number = 1;
+    while !rest.is_empty() {
+        let (pair, lines, next) =
+            parse_entry(rest).with_context(|| format!("Invalid entry on line {line_number}"))?;
+        pairs.extend(pair);
+        line_number += lines;
+        rest = next;
+    }
+    Ok(pairs)
+}
+
+/// Parse the entry at the start of the input, if it isn't a blank or comment line
+///
+/// Returns the number of lines it spans and the remaining input.
+fn parse_entry(input: &str) -> Result<(Option<Pair>, usize, &str)> {
+    let (line, next) = split_line(input);
+    let trimmed = line.trim_start();
+    if trimmed.trim_end().is_empty() || trimmed.starts_with('#') {
+        return Ok((None, 1, next));
+    }
+    let trimmed = trimmed
+        .strip_prefix("export ")
+        .map_or(trimmed, str::trim_start);
+    let (name, value) = trimmed
+        .split_once('=')
+        .context("Expected a NAME=value line")?;
+    let name = name.trim_end();
+    ensure!(!name.is_empty(), "Missing a name before =");
+    let value = value.trim_start_matches([' ', '\t']);
+    let quote = match value.chars().next() {
+        Some(quote @ ('"' | '\'')) => quote,
+        _ => {
+            let value = match value.find(" #").or_else(|| value.find("\t#")) {
+                Some(comment) => &value[..comment],
+                None if value.starts_with('#') => "",
+                None => value,
+            };
+            return Ok((Some((name.to_owned(), value.trim().to_owned())), 1, next));
+        }
+    };
+    // The value is a suffix of the line, continue from there as it may span several lines
+    let start = line.len() - value.len() + 1;
+    let (value, length) = parse_quoted(&input[start..], quote)?;
+    let end = start + length;
+    let (after, next) = split_line(&input[end..]);
+    let after = after.trim();
+    ensure!(
+        after.is_empty() || after.starts_with('#'),
+        "Unexpected characters after the closing quote"
+    );
+    let lines = input[..end].matches('\n').count() + 1;
+    Ok((Some((name.to_owned(), value)), lines, next))
+}
+
+/// Parse a quoted value up to and including its closing quote, returning its length
+fn parse_quoted(input: &str, quote: char) -> Result<(String, usize)> {
+    let mut value = String::new();
+    let mut chars = input.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            c if c == quote => return Ok((value, i + 1)),
+            '\\' if quote == '"' => match chars.next() {
+                Some((_, 'n')) => value.push('\n'),
+                Some((_, 'r')) => value.push('\r'),
+                Some((_, 't')) => value.push('\t'),
+                Some((_, c @ ('"' | '\\'))) => value.push(c),
+                // Keep unknown escapes as they are
+                Some((_, c)) => {
+                    value.push('\\');
+                    value.push(c);
+                }
+                None => break,
+            },
+            c => value.push(c),
+        }
+    }
+    bail!("Missing closing quote {quote}")
+}
+
+/// Split off the first line, without its line ending
+fn split_line(input: &str) -> (&str, &str) {
+    let (line, rest) = input.split_once('\n').unwrap_or((input, ""));
+    (line.strip_suffix('\r').unwrap_or(line), rest)
+}
@@ -0,0 +1,89 @@
+//! Editing decrypted secrets in the user's editor, via a private temporary file.
+
+use std::io::{Read, Seek, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use anyhow::*;
+use tempfile::NamedTempFile;
+
+/// A temporary file only readable by the current user, overwritten with zeros when dropped
+pub struct PrivateFile(NamedTempFile);
+
+impl PrivateFile {
+    /// Create the file with the given contents, preferring a memory backed directory
+    ///
+    /// The suffix, like `.env`, lets editors pick the right syntax highlighting.
+    pub fn new(contents: &[u8], suffix: &str) -> Result<Self> {
+        // Files created by tempfile are only readable and writable by their owner
+        let mut file = tempfile::Builder::new()
+            .prefix("amber-")
+            .suffix(suffix)
+            .tempfile_in(private_dir())
+            .context("Unable to create a temporary file")?;
+        file.write_all(contents)?;
+        file.as_file().sync_all()?;
+        Ok(PrivateFile(file))
+    }
+
+    pub fn path(&self) -> &Path {
+        self.0.path()
+    }
+
+    /// Open the file in the user's editor, returning its contents once the editor exits
+    pub fn edit(&self) -> Result<Vec<u8>> {
+        let editor = editor();
+        log::debug!("Editing {} with {editor}", self.path().display());
+        let mut words = editor.split_whitespace();
+        let program = words.next().context("The editor command is empty")?;
+        let status = Command::new(program)
+            .args(words)
+            .arg(self.path())
+            .status()
+            .with_context(|| format!("Unable to run the editor {editor}"))?;
+        ensure!(status.success(), "The editor {editor} failed: {status}");
+        Ok(fs_err::read(self.path())?)
+    }
+
+    /// Overwrite the contents with zeros, so they don't linger on disk after removal
+    fn shred(&mut self) -> Result<()> {
+        // Editors may replace the file rather than write to it, open it again by path
+        let mut file = fs_err::OpenOptions::new().write(true).open(self.path())?;
+        let len = file.metadata()?.len();
+        file.rewind()?;
+        std::io::copy(&mut std::io::repeat(0).take(len), &mut file)?;
+        file.sync_all()?;
+        Ok(())
+    }
+}
+
+impl Drop for PrivateFile {
+    fn drop(&mut self) {
+        if let Err(e) = self.shred() {
+            log::warn!(
+                "Unable to overwrite the temporary file {}: {e:?}",
+                self.path().display()
+            );
+        }
+        // The file itself is removed when dropping the NamedTempFile
+    }
+}
+
+/// The editor to use: $VISUAL, then $EDITOR, then vi
+fn editor() -> String {
+    ["VISUAL", "EDITOR"]
+        .iter()
+        .filter_map(|var| std::env::var(var).ok())
+        .find(|editor| !editor.trim().is_empty())
+        .unwrap_or_else(|| "vi".to_owned())
+}
+
+/// A directory for private files, preferring tmpfs so decrypted values never reach a disk
+fn private_dir() -> PathBuf {
+    std::env::var_os("XDG_RUNTIME_DIR")
+        .map(PathBuf::from)
+        .into_iter()
+        .chain([PathBuf::from("/dev/shm")])
+        .find(|dir| dir.is_dir())
+        .unwrap_or_else(std::env::temp_dir)
+}
@@ -0,0 +1,200 @@
+//! Reading amber files from git history, using the local `git` binary.
+
+use std::ffi::OsStr;
+use std::path::{Component, Path, PathBuf};
+use std::process::Command;
+
+use anyhow::*;
+
+use crate::config::Files;
+
+/// A commit of the git repository containing the current directory
+pub struct Revision {
+    /// Commit ID the revision resolved to
+    commit: String,
+    /// Root of the working tree, which paths in the commit are relative to
+    root: PathBuf,
+    /// Directory relative paths are resolved against
+    current_dir: PathBuf,
+}
+
+/// A commit, as listed by [Revision::history]
+#[derive(Debug)]
+pub struct Commit {
+    pub id: String,
+    /// Name and email address
+    pub author: String,
+    /// Author date, like 2025-06-30
+    pub date: String,
+    /// First line of the commit message
+    pub subject: String,
+}
+
+impl Revision {
+    /// Resolve a revision like `HEAD~2`, a branch, a tag or a commit ID
+    pub fn new(rev: &str) -> Result<Self> {
+        let current_dir = std::env::current_dir()?;
+        let root = git(&current_dir, ["rev-parse", "--show-toplevel"])
+            .context("Reading from a git revision requires running inside a git repository")?;
+        let commit = git(
+            &current_dir,
+            [
+                "rev-parse",
+                "--verify",
+                "--quiet",
+                "--end-of-options",
+                &format!("{rev}^{{commit}}"),
+            ],
+        )
+        .with_context(|| format!("Unknown git revision {rev}"))?;
+        let commit = String::from_utf8(commit)?.trim().to_owned();
+        log::debug!("Reading from commit {commit}");
+        Ok(Revision {
+            commit,
+            root: String::from_utf8(root)?.trim().into(),
+            current_dir,
+        })
+    }
+
+    /// The same repository at another commit, e.g. one listed by [Revision::history]
+    pub fn at(&self, commit: &Commit) -> Revision {
+        Revision {
+            commit: commit.id.clone(),
+            root: self.root.clone(),
+            current_dir: self.current_dir.clone(),
+        }
+    }
+
+    /// Commits up to this revision changing any of the paths, newest first
+    pub fn history(&self, paths: &[PathBuf]) -> Result<Vec<Commit>> {
+        let mut args = vec![
+            "log".to_owned(),
+            "-z".to_owned(),
+            "--date=short".to_owned(),
+            "--format=%H%x1f%an <%ae>%x1f%ad%x1f%s".to_owned(),
+            self.commit.clone(),
+            "--".to_owned(),
+        ];
+        for path in paths {
+            let relative = self
+                .relative(path)
+                .with_context(|| format!("{} is outside of the git repository", path.display()))?;
+            args.push(relative);
+        }
+        let log = String::from_utf8(git(&self.root, args)?)?;
+        log.split('\0')
+            .filter(|entry| !entry.is_empty())
+            .map(|entry| {
+                let mut fields = entry.trim_start_matches('\n').split('\x1f');
+                let mut field = || fields.next().context("Invalid git log output");
+                Ok(Commit {
+                    id: field()?.to_owned(),
+                    author: field()?.to_owned(),
+                    date: field()?.to_owned(),
+                    subject: field()?.to_owned(),
+                })
+            })
+            .collect()
+    }
+
+    /// The path relative to the repository root, with forward slashes as git expects
+    ///
+    /// None for paths outside the repository.
+    fn relative(&self, path: &Path) -> Option<String> {
+        // Resolve `..` lexically, since the path may not exist in the working tree
+        let mut absolute = PathBuf::new();
+        for component in self.current_dir.join(path).components() {
+            match component {
+                Component::CurDir => {}
+                Component::ParentDir => {
+                    absolute.pop();
+                }
+                component => absolute.push(component),
+            }
+        }
+        let relative = absolute.strip_prefix(&self.root).ok()?;
+        let components: Option<Vec<&str>> = relative.iter().map(OsStr::to_str).collect();
+        Some(components?.join("/"))
+    }
+
+    /// Object name of a path in the commit, like `COMMIT:path/to/amber.yaml`
+    fn object(&self, path: &Path) -> Result<String> {
+        let relative = self.relative(path).with_context(|| {
+            format!(
+                "{} is outside of the git repository at {}",
+                path.display(),
+                self.root.display()
+            )
+        })?;
+        Ok(format!("{}:{}", self.commit, relative))
+    }
+
+    /// The type of the object at the path, if any: blob for files, tree for directories
+    fn object_type(&self, path: &Path) -> Option<String> {
+        let object = self.object(path).ok()?;
+        let object_type = git(&self.root, ["cat-file", "-t", &object]).ok()?;
+        Some(String::from_utf8_lossy(&object_type).trim().to_owned())
+    }
+}
+
+impl Files for Revision {
+    fn exists(&self, path: &Path) -> bool {
+        self.object_type(path).is_some()
+    }
+
+    fn is_dir(&self, path: &Path) -> bool {
+        self.object_type(path).as_deref() == Some("tree")
+    }
+
+    fn read(&self, path: &Path) -> Result<Vec<u8>> {
+        let object = self.object(path)?;
+        git(&self.root, ["cat-file", "blob", &object]).with_context(|| {
+            format!(
+                "Unable to read {} at commit {}",
+                path.display(),
+                self.commit
+            )
+        })
+    }
+
+    fn list(&self, dir: &Path) -> Result<Vec<(String, bool)>> {
+        if !self.is_dir(dir) {
+            return Ok(vec![]);
+        }
+        let listing = git(&self.root, ["ls-tree", "-z", &self.object(dir)?])?;
+        let mut entries = Vec::new();
+        for entry in listing.split(|b| *b == 0).filter(|entry| !entry.is_empty()) {
+            // Entries look like "<mode> <type> <object>\t<name>"
+            let entry = std::str::from_utf8(entry)?;
+            let (info, name) = entry
+                .split_once('\t')
+                .context("Invalid git ls-tree output")?;
+            entries.push((name.to_owned(), info.split(' ').nth(1) == Some("tree")));
+        }
+        Ok(entries)
+    }
+
+    fn identify(&self, path: &Path) -> Result<PathBuf> {
+        Ok(self.object(path)?.into())
+    }
+}
+
+/// Run git, returning its output
+fn git<I, S>(dir: &Path, args: I) -> Result<Vec<u8>>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<OsStr>,
+{
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        .output()
+        .context("Unable to run git, is it on your PATH?")?;
+    ensure!(
+        output.status.success(),
+        "git failed: {}",
+        String::from_utf8_lossy(&output.stderr).trim()
+    );
+    Ok(output.stdout)
+}
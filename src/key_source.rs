@@ -0,0 +1,221 @@
+//! Locating and parsing the secret key used for decryption.
+
+use std::collections::BTreeMap;
+use std::convert::TryInto;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use anyhow::*;
+use crypto_box::{PublicKey, SecretKey};
+use serde::{Deserialize, Serialize};
+
+use crate::config::SECRET_KEY_ENV;
+use crate::passphrase;
+
+/// Environment variable name containing the path to a secret key file
+pub const SECRET_KEY_FILE_ENV: &str = "AMBER_SECRET_FILE";
+
+/// Environment variable set by systemd to the directory holding a service's credentials
+const CREDENTIALS_DIRECTORY_ENV: &str = "CREDENTIALS_DIRECTORY";
+
+/// Name of the systemd credential holding the secret key, e.g. `LoadCredential=amber-secret:...`
+const CREDENTIAL_NAME: &str = "amber-secret";
+
+/// Prefix of key provider plugin executables, followed by the plugin name
+const PLUGIN_PREFIX: &str = "amber-key-";
+
+/// Version of the plugin protocol, sent with every request
+const PLUGIN_PROTOCOL_VERSION: u32 = 1;
+
+/// Where to look for the secret key
+#[derive(Debug, Clone, Default)]
+pub struct KeySource {
+    /// Explicitly provided secret key file, including `/dev/fd/N` paths
+    pub file: Option<PathBuf>,
+    /// Name of the key provider plugin, run as `amber-key-<name>`
+    pub plugin: Option<String>,
+}
+
+/// Request written as JSON to a plugin's stdin
+#[derive(Serialize)]
+struct PluginRequest<'a> {
+    version: u32,
+    /// Recipients of the amber file, the plugin should return a secret key for one of them
+    recipients: Vec<PluginRecipient<'a>>,
+}
+
+#[derive(Serialize)]
+struct PluginRecipient<'a> {
+    name: &'a str,
+    /// Hex encoded public key
+    public_key: String,
+}
+
+/// Response read as JSON from a plugin's stdout, exactly one field must be set
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PluginResponse {
+    /// Hex encoded secret key
+    secret_key: Option<String>,
+    /// Explanation of why no secret key was provided
+    error: Option<String>,
+}
+
+impl KeySource {
+    /// Was any secret key provided, without trying to load it?
+    pub fn is_provided(&self) -> bool {
+        self.file.is_some()
+            || self.plugin.is_some()
+            || std::env::var_os(SECRET_KEY_ENV).is_some()
+            || std::env::var_os(CREDENTIALS_DIRECTORY_ENV)
+                .map_or(false, |dir| Path::new(&dir).join(CREDENTIAL_NAME).exists())
+    }
+
+    /// Load the secret key for one of the given recipients, checking in order:
+    ///
+    /// 1. The explicitly provided secret key file
+    /// 2. The configured key provider plugin
+    /// 3. The `AMBER_SECRET` environment variable
+    /// 4. The `amber-secret` credential in systemd's `$CREDENTIALS_DIRECTORY`
+    pub fn load(&self, recipients: &BTreeMap<String, PublicKey>) -> Result<SecretKey> {
+        if let Some(file) = &self.file {
+            return load_file(file);
+        }
+        if let Some(plugin) = &self.plugin {
+            return run_plugin(plugin, recipients);
+        }
+        if let Some(hex) = std::env::var_os(SECRET_KEY_ENV) {
+            return (|| {
+                let hex = hex.into_string().ok().context("Invalid UTF-8 encoding")?;
+                parse_secret_key(&hex)
+            })()
+            .with_context(|| {
+                format!("Malformed secret key in environment variable {SECRET_KEY_ENV}")
+            });
+        }
+        if let Some(dir) = std::env::var_os(CREDENTIALS_DIRECTORY_ENV) {
+            let file = Path::new(&dir).join(CREDENTIAL_NAME);
+            if file.exists() {
+                return load_file(&file);
+            }
+            log::debug!("No credential found at {}", file.display());
+        }
+        Err(anyhow!(
+            "No secret key provided. Set the {SECRET_KEY_ENV} environment variable, \
+             point --secret-key-file or {SECRET_KEY_FILE_ENV} to a file containing it, \
+             or use a key provider plugin with --key-plugin"
+        ))
+    }
+}
+
+/// Read a secret key from a file, refusing files other users can read
+///
+/// Passphrase protected key files are recognized automatically, prompting for the passphrase.
+fn load_file(path: &Path) -> Result<SecretKey> {
+    ensure!(
+        path.exists(),
+        "Secret key file {} does not exist",
+        path.display()
+    );
+    check_permissions(path)?;
+    let contents = fs_err::read_to_string(path)?;
+    if passphrase::is_encrypted(&contents) {
+        let passphrase = passphrase::read_passphrase(path)?;
+        return passphrase::decrypt(&contents, &passphrase).with_context(|| {
+            format!(
+                "Unable to decrypt passphrase protected secret key in file {}",
+                path.display()
+            )
+        });
+    }
+    parse_secret_key(&contents)
+        .with_context(|| format!("Malformed secret key in file {}", path.display()))
+}
+
+/// Ask a key provider plugin for the secret key
+///
+/// The plugin's stderr is inherited, so it can interact with the user, e.g. to unlock a
+/// password manager.
+fn run_plugin(plugin: &str, recipients: &BTreeMap<String, PublicKey>) -> Result<SecretKey> {
+    let exe = format!("{PLUGIN_PREFIX}{plugin}");
+    (|| {
+        ensure!(
+            !plugin.is_empty() && !plugin.contains(std::path::is_separator),
+            "Invalid plugin name"
+        );
+        let request = PluginRequest {
+            version: PLUGIN_PROTOCOL_VERSION,
+            recipients: recipients
+                .iter()
+                .map(|(name, public_key)| PluginRecipient {
+                    name,
+                    public_key: hex::encode(public_key),
+                })
+                .collect(),
+        };
+        log::debug!("Running key plugin {}", exe);
+        let mut child = Command::new(&exe)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .spawn()
+            .with_context(|| format!("Unable to launch {exe}, is it on your PATH?"))?;
+        let mut stdin = child.stdin.take().context("No stdin available")?;
+        serde_json::to_writer(&mut stdin, &request)?;
+        stdin.write_all(b"\n")?;
+        std::mem::drop(stdin);
+        let output = child.wait_with_output()?;
+        ensure!(
+            output.status.success(),
+            "Plugin exited with {}",
+            output.status
+        );
+        let response: PluginResponse =
+            serde_json::from_slice(&output.stdout).context("Invalid response from plugin")?;
+        match (response.secret_key, response.error) {
+            (Some(secret_key), None) => {
+                parse_secret_key(&secret_key).context("Malformed secret key from plugin")
+            }
+            (None, Some(error)) => Err(anyhow!("Plugin reported an error: {error}")),
+            _ => Err(anyhow!(
+                "Plugin response must contain exactly one of secret_key and error"
+            )),
+        }
+    })()
+    .with_context(|| format!("Error loading secret key from plugin {exe}"))
+}
+
+#[cfg(unix)]
+fn check_permissions(path: &Path) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let metadata = fs_err::metadata(path)?;
+    // Pipes such as /dev/fd/N from process substitution are never shared with other users
+    if metadata.is_file() {
+        let mode = metadata.permissions().mode() & 0o777;
+        ensure!(
+            mode & 0o007 == 0,
+            "Secret key file {} is accessible by other users (mode {:o}), please run: chmod 600 {}",
+            path.display(),
+            mode,
+            path.display()
+        );
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn check_permissions(_path: &Path) -> Result<()> {
+    Ok(())
+}
+
+/// Parse a hex encoded secret key, ignoring surrounding whitespace
+pub fn parse_secret_key(hex: &str) -> Result<SecretKey> {
+    let bs: [u8; 32] = hex::decode(hex.trim())
+        .ok()
+        .context("Invalid hex encoding")?
+        .try_into()
+        .map_err(|_| anyhow!("Invalid secret key, expected 32 bytes"))?;
+    Ok(SecretKey::from(bs))
+}
@@ -0,0 +1,138 @@
+//! Amber files extending other amber files.
+//!
+//! In a monorepo, a service's amber file can extend a shared one at the root with `extends`,
+//! overriding some of its secrets. Commands reading secrets see the merged result.
+
+use std::collections::{BTreeMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use anyhow::*;
+use crypto_box::SecretKey;
+
+use crate::config::{Config, Files, ValueKind};
+use crate::key_source::KeySource;
+
+/// An amber file, as part of a chain of files extending each other
+pub struct Layer {
+    /// Location of the file, as found by following `extends`
+    pub path: PathBuf,
+    pub config: Config,
+}
+
+/// A secret value taken from one of the layers
+pub struct LayeredSecret<'a> {
+    pub name: String,
+    /// Text value, base64 encoded for binary values
+    pub value: String,
+    pub kind: ValueKind,
+    /// The layer the value came from
+    pub source: &'a Layer,
+}
+
+/// The chain of files, starting with the most specific one
+pub struct Layers(Vec<Layer>);
+
+impl Layers {
+    /// Load a file and every file it extends, selecting the given environment where it exists
+    pub fn load(files: &dyn Files, path: &Path, environment: Option<&str>) -> Result<Self> {
+        let mut layers = Vec::new();
+        let mut seen = HashSet::new();
+        let mut next = Some(path.to_owned());
+        while let Some(path) = next {
+            ensure!(
+                seen.insert(files.identify(&path)?),
+                "{} is part of a cycle of amber files extending each other",
+                path.display()
+            );
+            let config = Config::load_from(files, &path)?;
+            next = config.extends().map(|extends| {
+                let parent = path.parent().unwrap_or_else(|| Path::new(""));
+                parent.join(extends)
+            });
+            layers.push(Layer { path, config });
+        }
+        if let Some(environment) = environment {
+            ensure!(
+                layers
+                    .iter()
+                    .any(|layer| layer.config.has_environment(environment)),
+                "No environment named {environment}"
+            );
+            for layer in &mut layers {
+                if layer.config.has_environment(environment) {
+                    layer
+                        .config
+                        .select_environment(Some(environment.to_owned()))?;
+                }
+            }
+        }
+        Ok(Layers(layers))
+    }
+
+    /// Load the secret key for every layer
+    ///
+    /// Keys are only loaded again for layers not accepting the ones loaded before.
+    pub fn load_secret_keys(&self, source: &KeySource) -> Result<Vec<SecretKey>> {
+        let mut keys: Vec<SecretKey> = Vec::with_capacity(self.0.len());
+        for layer in &self.0 {
+            let key = match keys.iter().find(|key| layer.config.accepts(key)) {
+                Some(key) => key.clone(),
+                None => self.load_secret_key(layer, source)?,
+            };
+            keys.push(key);
+        }
+        Ok(keys)
+    }
+
+    fn load_secret_key(&self, layer: &Layer, source: &KeySource) -> Result<SecretKey> {
+        let key = layer.config.load_secret_key(source);
+        if self.0.len() == 1 {
+            key
+        } else {
+            key.with_context(|| format!("Unable to decrypt {}", layer.path.display()))
+        }
+    }
+
+    /// Decrypt the secrets of every layer, more specific files taking precedence
+    ///
+    /// Takes the keys returned by [Layers::load_secret_keys], and returns secrets sorted by name.
+    pub fn secrets(&self, keys: &[SecretKey]) -> Result<Vec<LayeredSecret>> {
+        let mut merged = BTreeMap::new();
+        for (layer, key) in self.0.iter().zip(keys).rev() {
+            for pair in layer.config.iter_secrets(key) {
+                let (name, value) = pair?;
+                let kind = layer.config.value_kind(name).unwrap_or_default();
+                merged.insert(name.clone(), (value, kind, layer));
+            }
+        }
+        Ok(merged
+            .into_iter()
+            .map(|(name, (value, kind, source))| LayeredSecret {
+                name,
+                value,
+                kind,
+                source,
+            })
+            .collect())
+    }
+
+    /// Decrypt a single secret to its raw bytes, from the most specific file containing it
+    pub fn get_secret(&self, key: &str, source: &KeySource) -> Result<Vec<u8>> {
+        let layer = self.find(key)?;
+        let secret_key = self.load_secret_key(layer, source)?;
+        layer.config.get_secret(key, &secret_key)
+    }
+
+    /// What kind of value the given secret holds, see [Layers::get_secret]
+    pub fn value_kind(&self, key: &str) -> Result<ValueKind> {
+        Ok(self.find(key)?.config.value_kind(key).unwrap_or_default())
+    }
+
+    /// The most specific file containing the secret
+    fn find(&self, key: &str) -> Result<&Layer> {
+        self.0
+            .iter()
+            .find(|layer| layer.config.value_kind(key).is_some())
+            .with_context(|| format!("Key does not exist: {key}"))
+    }
+}
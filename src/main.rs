@@ -1,55 +1,211 @@
 mod cli;
 mod config;
+mod dotenv;
+mod editor;
 mod exec;
+mod git;
+mod key_source;
+mod layers;
 mod mask;
+mod passphrase;
+mod shell;
 
-use std::{io::Read, path::Path};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    io::{Read, Write},
+    path::{Path, PathBuf},
+};
 
 use anyhow::*;
 use base64::Engine;
-use crypto_box::{aead::OsRng, SecretKey};
+use chrono::{DateTime, SecondsFormat, Utc};
+use config::Files;
+use crypto_box::{aead::OsRng, PublicKey, SecretKey};
 use exec::CommandExecExt;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
 #[derive(Serialize)]
 struct KeyValue<'a> {
     key: &'a str,
     value: &'a str,
-}
-
-impl<'a, K, V> From<&'a (K, V)> for KeyValue<'a>
-where
-    K: AsRef<str>,
-    V: AsRef<str>,
-{
-    fn from((key, value): &'a (K, V)) -> Self {
-        KeyValue {
-            key: key.as_ref(),
-            value: value.as_ref(),
-        }
-    }
+    /// Set to `base64` for binary values
+    #[serde(skip_serializing_if = "Option::is_none")]
+    encoding: Option<&'static str>,
+    /// File the value came from, with `print --show-source`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    source: Option<&'a str>,
 }
 
 fn main() -> Result<()> {
     let cmd = cli::init();
     log::debug!("{:?}", cmd);
     match cmd.sub {
-        cli::SubCommand::Init { only_secret_key } => init(cmd.opt, only_secret_key),
-        cli::SubCommand::Encrypt { key, value } => encrypt(cmd.opt, key, value),
-        cli::SubCommand::Generate { key } => generate(cmd.opt, key),
+        cli::SubCommand::Init {
+            only_secret_key,
+            recipient,
+            passphrase,
+            extends,
+            layout,
+        } => init(
+            cmd.opt,
+            only_secret_key,
+            &recipient,
+            passphrase,
+            extends,
+            layout,
+        ),
+        cli::SubCommand::Encrypt {
+            key,
+            value,
+            file,
+            binary,
+            metadata,
+        } => {
+            let kind = if binary {
+                config::ValueKind::Binary
+            } else {
+                config::ValueKind::Text
+            };
+            let value = match (value, file) {
+                (Some(value), _) => Value::Given(value),
+                (None, Some(file)) => Value::File(file),
+                (None, None) => Value::Stdin,
+            };
+            encrypt(cmd.opt, key, value, kind, metadata)
+        }
+        cli::SubCommand::Generate { key, metadata } => generate(cmd.opt, key, metadata),
         cli::SubCommand::Remove { key } => remove(cmd.opt, key),
-        cli::SubCommand::Print { style } => print(cmd.opt, style),
+        cli::SubCommand::Import {
+            file,
+            format,
+            from_env,
+            dry_run,
+            overwrite,
+            skip_existing,
+        } => {
+            let source = match (file, from_env) {
+                (_, Some(prefix)) => ImportSource::Environment(prefix),
+                (Some(file), None) => {
+                    let format = format.unwrap_or_else(|| cli::ImportFormat::guess(&file));
+                    ImportSource::File(file, format)
+                }
+                (None, None) => unreachable!("Required by clap"),
+            };
+            let existing = if overwrite {
+                Existing::Overwrite
+            } else if skip_existing {
+                Existing::Skip
+            } else {
+                Existing::Fail
+            };
+            import(cmd.opt, source, dry_run, existing)
+        }
+        cli::SubCommand::Rename { old, new } => rename(cmd.opt, &old, new),
+        cli::SubCommand::Copy { key, to, to_env } => copy(cmd.opt, &key, to, to_env),
+        cli::SubCommand::Edit { format } => edit(cmd.opt, format),
+        cli::SubCommand::Print { style, show_source } => print(cmd.opt, style, show_source),
+        cli::SubCommand::List { long, json } => list(cmd.opt, long, json),
+        cli::SubCommand::Check {
+            warn_within,
+            fail_within,
+        } => check(cmd.opt, &warn_within, &fail_within),
         cli::SubCommand::Exec { cmd: cmd_, args } => exec(cmd.opt, cmd_, args),
+        cli::SubCommand::Get {
+            key,
+            no_newline,
+            json,
+        } => get(cmd.opt, &key, no_newline, json),
         cli::SubCommand::WriteFile { key, dest } => write_file(cmd.opt, &key, &dest),
+        cli::SubCommand::RotateKey {
+            only_secret_key,
+            recipient,
+        } => rotate_key(cmd.opt, only_secret_key, recipient.as_deref()),
+        cli::SubCommand::Recipient { sub } => recipient(cmd.opt, sub),
+        cli::SubCommand::Env { sub } => environment(cmd.opt, sub),
+        cli::SubCommand::Migrate { check } => migrate(cmd.opt, check),
+        cli::SubCommand::Convert { layout, dest } => convert(cmd.opt, layout, dest),
+        cli::SubCommand::MergeDriver { base, ours, theirs } => {
+            merge_driver(cmd.opt, &base, &ours, &theirs)
+        }
+        cli::SubCommand::Textconv { file, values } => textconv(cmd.opt, &file, values),
+        cli::SubCommand::Log { key, show_values } => log(cmd.opt, key, show_values),
+        cli::SubCommand::Diff {
+            left,
+            right,
+            left_env,
+            right_env,
+            left_secret_key_file,
+            right_secret_key_file,
+            json,
+        } => {
+            let env = cmd.opt.env.clone();
+            diff(
+                cmd.opt,
+                DiffSide::new(left, left_env.or_else(|| env.clone()), left_secret_key_file),
+                DiffSide::new(right, right_env.or(env), right_secret_key_file),
+                json,
+            )
+        }
     }
 }
 
-fn init(mut opt: cli::Opt, only_secret_key: bool) -> Result<()> {
-    let (secret_key, config) = config::Config::new();
+fn init(
+    mut opt: cli::Opt,
+    only_secret_key: bool,
+    recipient: &str,
+    passphrase: bool,
+    extends: Option<PathBuf>,
+    layout: config::Layout,
+) -> Result<()> {
+    reject_rev(&opt)?;
+    ensure!(
+        opt.env.is_none(),
+        "New files only contain defaults, add environments with amber env add"
+    );
+    let (secret_key, mut config) = config::Config::new(recipient)?;
+    config.set_layout(layout);
+    if let Some(extends) = extends {
+        let parent = opt.find_amber_yaml_or_default(layout).parent();
+        let parent = parent.unwrap_or_else(|| Path::new("")).join(&extends);
+        config::Config::load(parent)?;
+        config.set_extends(Some(extends));
+    }
+
+    if passphrase {
+        let key_file = opt.secret_key_file.clone().with_context(|| {
+            format!(
+                "--passphrase requires --secret-key-file or {} to know where to write the key",
+                key_source::SECRET_KEY_FILE_ENV
+            )
+        })?;
+        let passphrase = passphrase::read_new_passphrase()?;
+        passphrase::write_key_file(&key_file, &secret_key, &passphrase)?;
+        config.save(opt.find_amber_yaml_or_default(layout))?;
+        eprintln!(
+            "Your secret key was written to {}, protected by your passphrase",
+            key_file.display()
+        );
+        eprintln!(
+            "If you lose the file or forget the passphrase, you will lose access to your secrets."
+        );
+        println!(
+            "export {}={}",
+            key_source::SECRET_KEY_FILE_ENV,
+            key_file.display()
+        );
+        return Ok(());
+    }
+
     let secret_key = hex::encode(secret_key.to_bytes());
 
-    config.save(opt.find_amber_yaml_or_default())?;
+    config.save(opt.find_amber_yaml_or_default(layout))?;
+
+    print_secret_key(&secret_key, only_secret_key);
+    Ok(())
+}
 
+/// Display a newly generated secret key to the user
+fn print_secret_key(secret_key: &str, only_secret_key: bool) {
     if only_secret_key {
         print!("{secret_key}");
     } else {
@@ -61,9 +217,46 @@ fn init(mut opt: cli::Opt, only_secret_key: bool) -> Result<()> {
         eprintln!("If you're using this for CI, please update your CI configuration with a secret environment variable");
         println!("export {}={}", config::SECRET_KEY_ENV, secret_key);
     }
+}
+
+/// Fail if `--rev` was given to a command that doesn't read from git revisions
+fn reject_rev(opt: &cli::Opt) -> Result<()> {
+    ensure!(
+        opt.rev.is_none(),
+        "--rev is only supported by commands reading secrets: print, exec, get and write-file"
+    );
     Ok(())
 }
 
+/// Load the amber file, selecting the environment given on the command line
+fn load_config(opt: &mut cli::Opt) -> Result<(PathBuf, config::Config)> {
+    reject_rev(opt)?;
+    let environment = opt.env.clone();
+    let amber_yaml = opt.find_amber_yaml()?.to_owned();
+    let mut config = config::Config::load(&amber_yaml)?;
+    config.select_environment(environment)?;
+    Ok((amber_yaml, config))
+}
+
+/// Load the amber file along with the files it extends, see [load_config]
+///
+/// Reads from the git revision given with `--rev`, if any.
+fn load_layers(opt: &mut cli::Opt) -> Result<layers::Layers> {
+    let environment = opt.env.clone();
+    match opt.rev.clone() {
+        None => layers::Layers::load(
+            &config::WorkTree,
+            opt.find_amber_yaml()?,
+            environment.as_deref(),
+        ),
+        Some(rev) => {
+            let revision = git::Revision::new(&rev)?;
+            let amber_yaml = opt.find_amber_yaml_in(&revision)?.to_owned();
+            layers::Layers::load(&revision, &amber_yaml, environment.as_deref())
+        }
+    }
+}
+
 fn validate_key(key: &str) -> Result<()> {
     ensure!(!key.is_empty(), "Cannot provide an empty key");
     if key
@@ -78,92 +271,732 @@ fn validate_key(key: &str) -> Result<()> {
     }
 }
 
-fn encrypt(mut opt: cli::Opt, key: String, value: Option<String>) -> Result<()> {
+/// Where to get the value of a secret from
+enum Value {
+    Given(String),
+    File(PathBuf),
+    Stdin,
+}
+
+fn encrypt(
+    mut opt: cli::Opt,
+    key: String,
+    value: Value,
+    kind: config::ValueKind,
+    metadata: cli::MetadataOpt,
+) -> Result<()> {
     validate_key(&key)?;
-    let amber_yaml = opt.find_amber_yaml()?;
-    let mut config = config::Config::load(amber_yaml)?;
-    let value = value.map_or_else(
-        || {
+    let metadata = MetadataUpdate::parse(metadata)?;
+    let key_source = opt.key_source();
+    let (amber_yaml, mut config) = load_config(&mut opt)?;
+    let value = match value {
+        Value::Given(value) => value.into_bytes(),
+        Value::File(path) => fs_err::read(path)?,
+        Value::Stdin => {
             log::debug!("No value provided on command line, taking from stdin");
-            eprintln!("Enter secret value (send EOF when done)");
-            eprintln!();
+            if kind == config::ValueKind::Text {
+                eprintln!("Enter secret value (send EOF when done)");
+                eprintln!();
+            }
             let stdin = std::io::stdin();
             let mut stdin = stdin.lock();
-            let mut buffer = String::new();
-            stdin
-                .read_to_string(&mut buffer)
-                .map(|_size| buffer)
-                .map_err(anyhow::Error::new)
-        },
-        Ok,
-    )?;
-    config.encrypt(key, &value)?;
+            let mut buffer = Vec::new();
+            stdin.read_to_end(&mut buffer)?;
+            buffer
+        }
+    };
+    ensure!(
+        kind == config::ValueKind::Binary || std::str::from_utf8(&value).is_ok(),
+        "The value is not valid UTF-8 text, use --binary to store binary values"
+    );
+    let secret_key = config.try_load_secret_key(&key_source);
+    config.encrypt(key.clone(), &value, kind, secret_key.as_ref())?;
+    if !metadata.is_empty() {
+        metadata.apply(config.metadata_mut(&key)?);
+    }
     config.save(amber_yaml)
 }
 
-fn generate(opt: cli::Opt, key: String) -> Result<()> {
+/// Metadata changes requested on the command line
+///
+/// The outer option is whether to change a field, the inner one its new value.
+struct MetadataUpdate {
+    description: Option<Option<String>>,
+    owner: Option<Option<String>>,
+    tags: Option<Vec<String>>,
+    expires_at: Option<Option<DateTime<Utc>>>,
+    rotate_after: Option<Option<config::Period>>,
+}
+
+impl MetadataUpdate {
+    /// Validate the command line options, empty values clear a field
+    fn parse(opt: cli::MetadataOpt) -> Result<Self> {
+        fn non_empty<T>(
+            value: Option<String>,
+            f: impl FnOnce(&str) -> Result<T>,
+        ) -> Result<Option<Option<T>>> {
+            value
+                .map(|value| {
+                    Some(value)
+                        .filter(|value| !value.is_empty())
+                        .as_deref()
+                        .map(f)
+                        .transpose()
+                })
+                .transpose()
+        }
+        let tags = opt
+            .tags
+            .map(|tags| {
+                let mut unique = Vec::new();
+                for tag in tags.into_iter().filter(|tag| !tag.is_empty()) {
+                    config::validate_tag(&tag)?;
+                    if !unique.contains(&tag) {
+                        unique.push(tag);
+                    }
+                }
+                Ok(unique)
+            })
+            .transpose()?;
+        Ok(MetadataUpdate {
+            description: non_empty(opt.description, |s| Ok(s.to_owned()))?,
+            owner: non_empty(opt.owner, |s| Ok(s.to_owned()))?,
+            tags,
+            expires_at: non_empty(opt.expires_at, config::parse_time)?,
+            rotate_after: non_empty(opt.rotate_after, str::parse)?,
+        })
+    }
+
+    fn is_empty(&self) -> bool {
+        self.description.is_none()
+            && self.owner.is_none()
+            && self.tags.is_none()
+            && self.expires_at.is_none()
+            && self.rotate_after.is_none()
+    }
+
+    fn apply(self, metadata: &mut config::Metadata) {
+        if let Some(description) = self.description {
+            metadata.description = description;
+        }
+        if let Some(owner) = self.owner {
+            metadata.owner = owner;
+        }
+        if let Some(tags) = self.tags {
+            metadata.tags = tags;
+        }
+        if let Some(expires_at) = self.expires_at {
+            metadata.expires_at = expires_at;
+        }
+        if let Some(rotate_after) = self.rotate_after {
+            metadata.rotate_after = rotate_after;
+        }
+    }
+}
+
+fn generate(opt: cli::Opt, key: String, metadata: cli::MetadataOpt) -> Result<()> {
     let value = SecretKey::generate(&mut OsRng);
     let value = base64::engine::general_purpose::STANDARD.encode(value.to_bytes());
     let msg = format!("Your new secret value is {key}: {value}");
-    encrypt(opt, key, Some(value))?;
+    encrypt(
+        opt,
+        key,
+        Value::Given(value),
+        config::ValueKind::Text,
+        metadata,
+    )?;
     println!("{}", &msg);
     Ok(())
 }
 
 fn remove(mut opt: cli::Opt, key: String) -> Result<()> {
     validate_key(&key)?;
-    let amber_yaml = opt.find_amber_yaml()?;
-    let mut config = config::Config::load(amber_yaml)?;
+    let (amber_yaml, mut config) = load_config(&mut opt)?;
     config.remove(&key);
     config.save(amber_yaml)
 }
 
-fn print(mut opt: cli::Opt, style: cli::PrintStyle) -> Result<()> {
-    let config = config::Config::load(opt.find_amber_yaml()?)?;
-    let secret = config.load_secret_key()?;
-    let pairs: Result<Vec<_>> = config.iter_secrets(&secret).collect();
-    let mut pairs = pairs?;
-    pairs.sort_by(|x, y| x.0.cmp(y.0));
+/// Where `amber import` reads secrets from
+enum ImportSource {
+    /// A file, or stdin for `-`
+    File(PathBuf, cli::ImportFormat),
+    /// Environment variables starting with a prefix
+    Environment(String),
+}
 
-    fn to_objs<'a, K, V, I>(p: I) -> Vec<KeyValue<'a>>
-    where
-        I: IntoIterator<Item = &'a (K, V)>,
-        K: AsRef<str> + 'a,
-        V: AsRef<str> + 'a,
-    {
-        p.into_iter().map(KeyValue::from).collect::<Vec<_>>()
+/// What `amber import` does with secrets that already exist
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Existing {
+    Fail,
+    Overwrite,
+    Skip,
+}
+
+/// A secret read by `amber import`, as output by `amber print --style json`
+#[derive(Deserialize)]
+struct ImportedValue {
+    key: String,
+    value: String,
+    #[serde(default)]
+    encoding: Option<String>,
+}
+
+/// The shapes of JSON and YAML files accepted by `amber import`
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Imported {
+    List(Vec<ImportedValue>),
+    Mapping(BTreeMap<String, String>),
+}
+
+fn import(
+    mut opt: cli::Opt,
+    source: ImportSource,
+    dry_run: bool,
+    existing: Existing,
+) -> Result<()> {
+    let secrets = read_imported(source)?;
+    let mut names = BTreeSet::new();
+    for (name, _, _) in &secrets {
+        validate_key(name).with_context(|| format!("Invalid secret name {name}"))?;
+        ensure!(names.insert(name), "{name} is set more than once");
     }
-    match style {
-        cli::PrintStyle::SetEnv => pairs
+    let key_source = opt.key_source();
+    let (amber_yaml, mut config) = load_config(&mut opt)?;
+    if existing == Existing::Fail {
+        let conflicts: Vec<&str> = names
             .iter()
-            .for_each(|(key, value)| println!("export {key}={value:?}")),
+            .filter(|name| config.value_kind(name).is_some())
+            .map(|name| name.as_str())
+            .collect();
+        ensure!(
+            conflicts.is_empty(),
+            "Some secrets already exist: {}. Use --overwrite to replace them, or --skip-existing to leave them alone",
+            conflicts.join(", ")
+        );
+    }
+
+    let secret_key = if dry_run {
+        None
+    } else {
+        config.try_load_secret_key(&key_source)
+    };
+    for (name, value, kind) in secrets {
+        let exists = config.value_kind(&name).is_some();
+        if exists && existing == Existing::Skip {
+            eprintln!("Skipping existing {name}");
+            continue;
+        }
+        eprintln!("{} {name}", if exists { "Overwriting" } else { "Adding" });
+        if !dry_run {
+            config.encrypt(name, &value, kind, secret_key.as_ref())?;
+        }
+    }
+    if dry_run {
+        eprintln!("Dry run, no changes saved");
+        return Ok(());
+    }
+    config.save(amber_yaml)
+}
+
+/// Read the names, values and kinds of the secrets to import, in order
+fn read_imported(source: ImportSource) -> Result<Vec<(String, Vec<u8>, config::ValueKind)>> {
+    let text = |value: String| (value.into_bytes(), config::ValueKind::Text);
+    let (path, format) = match source {
+        ImportSource::Environment(prefix) => {
+            ensure!(!prefix.is_empty(), "The prefix must not be empty");
+            let mut secrets = Vec::new();
+            for (name, value) in std::env::vars_os() {
+                let Some(name) = name.to_str().filter(|name| name.starts_with(&prefix)) else {
+                    continue;
+                };
+                // Never store amber's own settings, such as the secret key
+                if name.starts_with("AMBER_") {
+                    log::warn!("Skipping {name}, which configures amber itself");
+                    continue;
+                }
+                let value = value
+                    .into_string()
+                    .ok()
+                    .with_context(|| format!("Invalid UTF-8 encoding in {name}"))?;
+                let (value, kind) = text(value);
+                secrets.push((name.to_owned(), value, kind));
+            }
+            secrets.sort_by(|a, b| a.0.cmp(&b.0));
+            return Ok(secrets);
+        }
+        ImportSource::File(path, format) => (path, format),
+    };
+    let contents = if path == Path::new("-") {
+        let mut contents = String::new();
+        std::io::stdin().read_to_string(&mut contents)?;
+        contents
+    } else {
+        fs_err::read_to_string(&path)?
+    };
+    let imported = match format {
+        cli::ImportFormat::Dotenv => {
+            return Ok(dotenv::parse(&contents)?
+                .into_iter()
+                .map(|(name, value)| {
+                    let (value, kind) = text(value);
+                    (name, value, kind)
+                })
+                .collect())
+        }
+        cli::ImportFormat::Json => serde_json::from_str(&contents).context(
+            "Expected a list of objects with key and value, or an object of names to strings",
+        )?,
+        cli::ImportFormat::Yaml => serde_yaml::from_str(&contents).context(
+            "Expected a list of mappings with key and value, or a mapping of names to strings",
+        )?,
+    };
+    match imported {
+        Imported::Mapping(mapping) => Ok(mapping
+            .into_iter()
+            .map(|(name, value)| {
+                let (value, kind) = text(value);
+                (name, value, kind)
+            })
+            .collect()),
+        Imported::List(values) => values
+            .into_iter()
+            .map(|imported| {
+                let (value, kind) = match imported.encoding.as_deref() {
+                    None => text(imported.value),
+                    Some("base64") => (
+                        base64::engine::general_purpose::STANDARD
+                            .decode(&imported.value)
+                            .with_context(|| {
+                                format!("Invalid base64 value for {}", imported.key)
+                            })?,
+                        config::ValueKind::Binary,
+                    ),
+                    Some(encoding) => {
+                        bail!("Unknown encoding {encoding} for {}", imported.key)
+                    }
+                };
+                Ok((imported.key, value, kind))
+            })
+            .collect(),
+    }
+}
+
+fn rename(mut opt: cli::Opt, old: &str, new: String) -> Result<()> {
+    validate_key(&new)?;
+    let key_source = opt.key_source();
+    let (amber_yaml, mut config) = load_config(&mut opt)?;
+    let secret_key = if config.binds_names() {
+        let secret_key = config.load_secret_key(&key_source).context(
+            "Renaming a secret requires the secret key, as its value is bound to its name",
+        )?;
+        Some(secret_key)
+    } else {
+        None
+    };
+    config.rename(old, new, secret_key.as_ref())?;
+    config.save(amber_yaml)
+}
+
+fn copy(mut opt: cli::Opt, key: &str, to: Option<PathBuf>, to_env: Option<String>) -> Result<()> {
+    ensure!(
+        to.is_some() || to_env.is_some(),
+        "Specify the amber file or the environment to copy to with --to or --to-env"
+    );
+    let key_source = opt.key_source();
+    let (amber_yaml, config) = load_config(&mut opt)?;
+    let secret_key = config.load_secret_key(&key_source)?;
+    let value = config.get_secret(key, &secret_key)?;
+    let kind = config.value_kind(key).unwrap_or_default();
+    let (_, metadata) = config
+        .metadata()
+        .into_iter()
+        .find(|(name, _)| *name == key)
+        .expect("Secret found above");
+    let metadata = metadata.clone();
+
+    let dest_path = to.unwrap_or(amber_yaml);
+    let mut dest = config::Config::load(&dest_path)?;
+    dest.select_environment(to_env)?;
+    // The digest of the value needs the destination's secret key, if we have it
+    let dest_key = Some(secret_key).filter(|secret_key| dest.accepts(secret_key));
+    dest.encrypt(key.to_owned(), &value, kind, dest_key.as_ref())?;
+    // Timestamps are the destination's own
+    let metadata = config::Metadata {
+        created_at: None,
+        updated_at: None,
+        ..metadata
+    };
+    if metadata.is_empty() {
+        return dest.save(dest_path);
+    }
+    if !dest.supports_metadata() {
+        log::warn!(
+            "Not copying the metadata of {key}, the destination file is of a version without metadata. Run amber migrate to upgrade it."
+        );
+        return dest.save(dest_path);
+    }
+    let dest_metadata = dest.metadata_mut(key)?;
+    dest_metadata.description = metadata.description;
+    dest_metadata.owner = metadata.owner;
+    dest_metadata.tags = metadata.tags;
+    dest_metadata.expires_at = metadata.expires_at;
+    dest_metadata.rotate_after = metadata.rotate_after;
+    dest.save(dest_path)
+}
+
+fn edit(mut opt: cli::Opt, format: cli::EditFormat) -> Result<()> {
+    let key_source = opt.key_source();
+    let (amber_yaml, mut config) = load_config(&mut opt)?;
+    let secret_key = config.load_secret_key(&key_source)?;
+    let mut original = BTreeMap::new();
+    let mut binary = BTreeSet::new();
+    for pair in config.iter_secrets(&secret_key) {
+        let (name, value) = pair?;
+        if config.value_kind(name) == Some(config::ValueKind::Binary) {
+            binary.insert(name.clone());
+        } else {
+            original.insert(name.clone(), value);
+        }
+    }
+
+    let inherited: BTreeSet<String> = original
+        .keys()
+        .filter(|name| config.is_inherited(name))
+        .cloned()
+        .collect();
+
+    let mut contents = format!(
+        "# Secrets of {}. Save and quit to encrypt the changes, remove a line to remove a secret.\n",
+        amber_yaml.display()
+    );
+    if let (Some(environment), false) = (&opt.env, inherited.is_empty()) {
+        contents += &format!(
+            "# Changing a default overrides it in environment {environment}, but defaults can only be removed without --env\n"
+        );
+    }
+    if !binary.is_empty() {
+        let names: Vec<&str> = binary.iter().map(String::as_str).collect();
+        contents += &format!(
+            "# Binary values can't be edited here: {}\n",
+            names.join(", ")
+        );
+    }
+    let suffix = match format {
+        cli::EditFormat::Dotenv => {
+            for (name, value) in &original {
+                contents += &dotenv::format_line(name, value);
+                contents.push('\n');
+            }
+            ".env"
+        }
+        cli::EditFormat::Yaml => {
+            if !original.is_empty() {
+                contents += &serde_yaml::to_string(&original)?;
+            }
+            ".yaml"
+        }
+    };
+
+    let file = editor::PrivateFile::new(contents.as_bytes(), suffix)?;
+    let edited = loop {
+        let edited = file.edit()?;
+        let edited = parse_edited(&edited, format, &binary).and_then(|edited| {
+            let removed: Vec<&str> = inherited
+                .iter()
+                .filter(|name| !edited.contains_key(*name))
+                .map(String::as_str)
+                .collect();
+            ensure!(
+                removed.is_empty(),
+                "Defaults can't be removed from an environment, edit them without --env to remove them: {}",
+                removed.join(", ")
+            );
+            Ok(edited)
+        });
+        match edited {
+            std::result::Result::Ok(edited) => break edited,
+            Err(e) => {
+                eprintln!("Error: {e:#}");
+                if !confirm("Edit the file again? [Y/n] ")? {
+                    bail!("Aborted, no changes made");
+                }
+            }
+        }
+    };
+    drop(file);
+
+    if edited == original {
+        eprintln!("No changes made");
+        return Ok(());
+    }
+    for name in original.keys().filter(|name| !edited.contains_key(*name)) {
+        log::info!("Removing secret {name}");
+        config.remove(name);
+    }
+    for (name, value) in edited {
+        match original.get(&name) {
+            Some(old) if *old == value => continue,
+            Some(_) => log::info!("Updating secret {name}"),
+            None => log::info!("Adding secret {name}"),
+        }
+        config.encrypt(
+            name,
+            value.as_bytes(),
+            config::ValueKind::Text,
+            Some(&secret_key),
+        )?;
+    }
+    config.save(amber_yaml)
+}
+
+/// Parse the secrets saved by the editor
+fn parse_edited(
+    edited: &[u8],
+    format: cli::EditFormat,
+    binary: &BTreeSet<String>,
+) -> Result<BTreeMap<String, String>> {
+    let edited = std::str::from_utf8(edited).context("The file is not valid UTF-8")?;
+    let pairs = match format {
+        cli::EditFormat::Dotenv => dotenv::parse(edited)?,
+        cli::EditFormat::Yaml => {
+            let mapping: Option<BTreeMap<String, serde_yaml::Value>> =
+                serde_yaml::from_str(edited)?;
+            let mut pairs = Vec::new();
+            for (name, value) in mapping.unwrap_or_default() {
+                let value = match value {
+                    serde_yaml::Value::String(value) => value,
+                    serde_yaml::Value::Number(value) => value.to_string(),
+                    serde_yaml::Value::Bool(value) => value.to_string(),
+                    _ => bail!("The value of {name} must be a string"),
+                };
+                pairs.push((name, value));
+            }
+            pairs
+        }
+    };
+    let mut secrets = BTreeMap::new();
+    for (name, value) in pairs {
+        validate_key(&name).with_context(|| format!("Invalid secret name {name}"))?;
+        ensure!(
+            !binary.contains(&name),
+            "{name} holds a binary value, which can't be edited here"
+        );
+        ensure!(
+            secrets.insert(name.clone(), value).is_none(),
+            "{name} is set more than once"
+        );
+    }
+    Ok(secrets)
+}
+
+/// Ask a yes or no question on the terminal, defaulting to yes
+///
+/// Answers no when stdin is closed.
+fn confirm(prompt: &str) -> Result<bool> {
+    eprint!("{prompt}");
+    let mut answer = String::new();
+    if std::io::stdin().read_line(&mut answer)? == 0 {
+        eprintln!();
+        return Ok(false);
+    }
+    Ok(matches!(
+        answer.trim().to_ascii_lowercase().as_str(),
+        "" | "y" | "yes"
+    ))
+}
+
+fn print(mut opt: cli::Opt, style: cli::PrintStyle, show_source: bool) -> Result<()> {
+    let key_source = opt.key_source();
+    let layers = load_layers(&mut opt)?;
+    let keys = layers.load_secret_keys(&key_source)?;
+    let pairs = layers.secrets(&keys)?;
+
+    let sources: Vec<_> = pairs
+        .iter()
+        .map(|secret| secret.source.path.display().to_string())
+        .collect();
+    let secrets: Vec<_> = pairs
+        .iter()
+        .zip(&sources)
+        .map(|(secret, source)| KeyValue {
+            key: &secret.name,
+            value: &secret.value,
+            encoding: match secret.kind {
+                config::ValueKind::Binary => Some("base64"),
+                config::ValueKind::Text => None,
+            },
+            source: Some(source.as_str()).filter(|_| show_source),
+        })
+        .collect();
+    let set_env = match style {
         cli::PrintStyle::Json => {
-            let secrets = to_objs(&pairs);
             serde_json::to_writer(std::io::stdout(), &secrets)?;
+            return Ok(());
         }
         cli::PrintStyle::Yaml => {
-            let secrets = to_objs(&pairs);
             serde_yaml::to_writer(std::io::stdout(), &secrets)?;
+            return Ok(());
+        }
+        cli::PrintStyle::SetEnv => |key: &str, value: &str| Ok(shell::posix(key, value)),
+        cli::PrintStyle::Fish => |key: &str, value: &str| Ok(shell::fish(key, value)),
+        cli::PrintStyle::PowerShell => |key: &str, value: &str| Ok(shell::powershell(key, value)),
+        cli::PrintStyle::Nushell => |key: &str, value: &str| Ok(shell::nushell(key, value)),
+        cli::PrintStyle::Cmd => shell::cmd,
+    };
+    // Check every value before printing anything, so that errors don't leave partial output
+    let lines = secrets
+        .iter()
+        .map(|secret| set_env(secret.key, secret.value))
+        .collect::<Result<Vec<_>>>()?;
+    for (secret, line) in secrets.iter().zip(lines) {
+        match (secret.source, &style) {
+            (Some(source), cli::PrintStyle::Cmd) => println!("rem {source}\n{line}"),
+            (Some(source), _) => println!("{line} # {source}"),
+            (None, _) => println!("{line}"),
         }
     }
 
     Ok(())
 }
 
+fn list(mut opt: cli::Opt, long: bool, json: bool) -> Result<()> {
+    #[derive(Serialize)]
+    struct Entry<'a> {
+        name: &'a str,
+        #[serde(skip_serializing_if = "config::ValueKind::is_text")]
+        kind: config::ValueKind,
+        #[serde(flatten)]
+        metadata: &'a config::Metadata,
+    }
+
+    let (_, config) = load_config(&mut opt)?;
+    let secrets = config.metadata();
+    if json {
+        let entries: Vec<_> = secrets
+            .iter()
+            .map(|(name, metadata)| Entry {
+                name,
+                kind: config.value_kind(name).unwrap_or_default(),
+                metadata,
+            })
+            .collect();
+        serde_json::to_writer(std::io::stdout(), &entries)?;
+        return Ok(());
+    }
+    for (name, metadata) in secrets {
+        println!("{name}");
+        if !long {
+            continue;
+        }
+        let kind = config.value_kind(name).unwrap_or_default();
+        let fields = [
+            (
+                "kind",
+                Some("binary".to_owned()).filter(|_| !kind.is_text()),
+            ),
+            ("description", metadata.description.clone()),
+            ("owner", metadata.owner.clone()),
+            (
+                "tags",
+                Some(metadata.tags.join(", ")).filter(|tags| !tags.is_empty()),
+            ),
+            ("created", metadata.created_at.map(format_time)),
+            ("updated", metadata.updated_at.map(format_time)),
+            ("expires", metadata.expires_at.map(format_time)),
+            (
+                "rotate after",
+                metadata.rotate_after.as_ref().map(ToString::to_string),
+            ),
+        ];
+        for (field, value) in fields {
+            if let Some(value) = value {
+                println!("    {field}: {value}");
+            }
+        }
+    }
+    Ok(())
+}
+
+fn format_time(time: DateTime<Utc>) -> String {
+    time.to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+fn check(
+    mut opt: cli::Opt,
+    warn_within: &config::Period,
+    fail_within: &config::Period,
+) -> Result<()> {
+    let (_, config) = load_config(&mut opt)?;
+    let now = Utc::now();
+    let mut failing = 0;
+    for (name, metadata) in config.metadata() {
+        if metadata.rotate_after.is_some() && metadata.updated_at.or(metadata.created_at).is_none()
+        {
+            log::warn!("Secret {name} has no timestamps, re-encrypt it to track its rotation");
+        }
+        let mut fails = false;
+        for (deadline, passed, upcoming) in [
+            (metadata.expires_at, "expired", "expires"),
+            (
+                metadata.rotation_due(),
+                "was due for rotation",
+                "is due for rotation",
+            ),
+        ] {
+            let deadline = match deadline {
+                Some(deadline) => deadline,
+                None => continue,
+            };
+            // Periods beyond the dates we can represent include every deadline
+            let within =
+                |period: &config::Period| period.after(now).map_or(true, |limit| deadline <= limit);
+            let due = within(fail_within);
+            fails |= due;
+            if deadline <= now {
+                let days = (now - deadline).num_days();
+                println!(
+                    "{name} {passed} on {} ({days} days ago)",
+                    format_time(deadline)
+                );
+            } else if within(warn_within) || due {
+                let days = (deadline - now).num_days();
+                println!(
+                    "{name} {upcoming} on {} (in {days} days)",
+                    format_time(deadline)
+                );
+            }
+        }
+        if fails {
+            failing += 1;
+        }
+    }
+    ensure!(
+        failing == 0,
+        "{failing} secret(s) expired or due for rotation{}",
+        if fail_within.duration().is_zero() {
+            String::new()
+        } else {
+            format!(" within {fail_within}")
+        }
+    );
+    Ok(())
+}
+
 fn exec(mut opt: cli::Opt, cmd: String, args: Vec<String>) -> Result<()> {
-    let config = config::Config::load(opt.find_amber_yaml()?)?;
-    let secret_key = config.load_secret_key()?;
+    let key_source = opt.key_source();
+    let layers = load_layers(&mut opt)?;
+    let keys = layers.load_secret_keys(&key_source)?;
 
     let mut cmd = std::process::Command::new(cmd);
     cmd.args(args);
 
     let mut secrets = Vec::new();
-    for pair in config.iter_secrets(&secret_key) {
-        let (name, value) = pair?;
-        log::debug!("Setting env var in child process: {}", name);
-        cmd.env(name, &value);
+    for secret in layers.secrets(&keys)? {
+        log::debug!("Setting env var in child process: {}", secret.name);
+        cmd.env(&secret.name, &secret.value);
         if !opt.unmasked {
-            secrets.push(value);
+            secrets.push(secret.value);
         }
     }
 
@@ -176,10 +1009,538 @@ fn exec(mut opt: cli::Opt, cmd: String, args: Vec<String>) -> Result<()> {
     Ok(())
 }
 
+fn get(mut opt: cli::Opt, key: &str, no_newline: bool, json: bool) -> Result<()> {
+    let key_source = opt.key_source();
+    let layers = load_layers(&mut opt)?;
+    let value = layers.get_secret(key, &key_source)?;
+    let kind = layers.value_kind(key)?;
+    if json {
+        let (value, encoding) = match kind {
+            config::ValueKind::Text => (String::from_utf8(value)?, None),
+            config::ValueKind::Binary => (
+                base64::engine::general_purpose::STANDARD.encode(value),
+                Some("base64"),
+            ),
+        };
+        let secret = KeyValue {
+            key,
+            value: &value,
+            encoding,
+            source: None,
+        };
+        serde_json::to_writer(std::io::stdout(), &secret)?;
+        return Ok(());
+    }
+    let mut stdout = std::io::stdout().lock();
+    stdout.write_all(&value)?;
+    if kind.is_text() && !no_newline {
+        stdout.write_all(b"\n")?;
+    }
+    stdout.flush()?;
+    Ok(())
+}
+
 fn write_file(mut opt: cli::Opt, key: &str, dest: &Path) -> Result<()> {
-    let config = config::Config::load(opt.find_amber_yaml()?)?;
-    let secret_key = config.load_secret_key()?;
-    let value = config.get_secret(key, &secret_key)?;
+    let key_source = opt.key_source();
+    let value = load_layers(&mut opt)?.get_secret(key, &key_source)?;
     std::fs::write(dest, value)
         .with_context(|| format!("Unable to write to file {}", dest.display()))
 }
+
+fn rotate_key(mut opt: cli::Opt, only_secret_key: bool, recipient: Option<&str>) -> Result<()> {
+    let key_source = opt.key_source();
+    let (amber_yaml, mut config) = load_config(&mut opt)?;
+    let secret_key = config.load_secret_key(&key_source)?;
+    let (new_secret_key, names) = config.rotate_key(recipient, &secret_key)?;
+    config.save(&amber_yaml)?;
+    report_reencrypted(&names);
+    eprintln!(
+        "Warning: older revisions of {} (e.g. in your Git history) can still be decrypted with the old key.",
+        amber_yaml.display()
+    );
+    print_secret_key(&hex::encode(new_secret_key.to_bytes()), only_secret_key);
+    Ok(())
+}
+
+fn migrate(mut opt: cli::Opt, check: bool) -> Result<()> {
+    let key_source = opt.key_source();
+    let (amber_yaml, mut config) = load_config(&mut opt)?;
+    if config.is_latest_version() {
+        eprintln!(
+            "{} is using the latest file format version {}",
+            amber_yaml.display(),
+            config.version()
+        );
+        return Ok(());
+    }
+    let old_version = config.version();
+    eprintln!(
+        "{} is using file format version {}, the latest is {}. Missing changes:",
+        amber_yaml.display(),
+        old_version,
+        config::Config::latest_version()
+    );
+    for change in config.pending_changes() {
+        eprintln!(
+            "* Version {}: {}{}",
+            change.version,
+            change.description,
+            if change.reencrypt {
+                " (re-encrypts every secret, requires a secret key)"
+            } else {
+                ""
+            }
+        );
+    }
+    if check {
+        bail!("File format is out of date, run amber migrate to upgrade");
+    }
+
+    let secret_key = if key_source.is_provided() {
+        Some(config.load_secret_key(&key_source)?)
+    } else {
+        None
+    };
+    let names = config.migrate(secret_key.as_ref())?;
+    if config.version() != old_version {
+        config.save(&amber_yaml)?;
+        report_reencrypted(&names);
+        eprintln!(
+            "Migrated {} from file format version {} to {}",
+            amber_yaml.display(),
+            old_version,
+            config.version()
+        );
+    }
+    ensure!(
+        config.is_latest_version(),
+        "Upgrading beyond file format version {} re-encrypts every secret, please provide a secret key",
+        config.version()
+    );
+    Ok(())
+}
+
+fn convert(mut opt: cli::Opt, layout: config::Layout, dest: Option<PathBuf>) -> Result<()> {
+    reject_rev(&opt)?;
+    let amber_yaml = opt.find_amber_yaml()?.to_owned();
+    let mut config = config::Config::load(&amber_yaml)?;
+    ensure!(
+        config.layout() != layout,
+        "{} already uses the {} layout",
+        amber_yaml.display(),
+        layout
+    );
+    let dest = dest.unwrap_or_else(|| amber_yaml.with_file_name(cli::default_file_name(layout)));
+    ensure!(!dest.exists(), "{} already exists", dest.display());
+    config.set_layout(layout);
+    config.save(&dest)?;
+    config::delete(&amber_yaml)?;
+    eprintln!(
+        "Converted {} to the {} layout in {}",
+        amber_yaml.display(),
+        layout,
+        dest.display()
+    );
+    Ok(())
+}
+
+fn merge_driver(opt: cli::Opt, base: &Path, ours: &Path, theirs: &Path) -> Result<()> {
+    reject_rev(&opt)?;
+    let conflicts = config::merge(base, ours, theirs)?;
+    for conflict in &conflicts {
+        eprintln!("Conflict: {conflict}");
+    }
+    ensure!(
+        conflicts.is_empty(),
+        "Unable to merge, resolve the conflicts, e.g. by encrypting conflicting secrets again, then mark the file as resolved"
+    );
+    Ok(())
+}
+
+fn textconv(opt: cli::Opt, file: &Path, values: config::ValueStyle) -> Result<()> {
+    reject_rev(&opt)?;
+    let config = config::Config::load(file)?;
+    let key_source = opt.key_source();
+    let secret_key = if values != config::ValueStyle::Masked && key_source.is_provided() {
+        // Older revisions may not be encrypted for this key yet
+        config
+            .load_secret_key(&key_source)
+            .map_err(|e| log::debug!("Showing masked values: {e:#}"))
+            .ok()
+    } else {
+        None
+    };
+    for line in config.render(secret_key.as_ref(), values)? {
+        println!("{line}");
+    }
+    Ok(())
+}
+
+fn log(mut opt: cli::Opt, key: String, show_values: bool) -> Result<()> {
+    let key_source = opt.key_source();
+    ensure!(
+        !show_values || key_source.is_provided(),
+        "--show-values requires a secret key"
+    );
+    let environment = opt.env.clone();
+    let revision = git::Revision::new(opt.rev.as_deref().unwrap_or("HEAD"))?;
+    let amber_yaml = opt.find_amber_yaml_in(&revision)?.to_owned();
+    // Follow the history across conversions between the default file and directory names
+    let mut paths = vec![amber_yaml.clone()];
+    let layouts = [config::Layout::File, config::Layout::Directory];
+    let default_names = layouts.map(cli::default_file_name);
+    if default_names
+        .iter()
+        .any(|name| amber_yaml.file_name() == Some(name.as_ref()))
+    {
+        for name in default_names {
+            let path = amber_yaml.with_file_name(name);
+            if !paths.contains(&path) {
+                paths.push(path);
+            }
+        }
+    }
+
+    let history = revision.history(&paths)?;
+    let mut configs = Vec::with_capacity(history.len() + 1);
+    for commit in &history {
+        let files = revision.at(commit);
+        let config = match paths.iter().find(|path| files.exists(path)) {
+            None => None,
+            Some(path) => {
+                let mut config = config::Config::load_from(&files, path)
+                    .with_context(|| format!("Unable to read commit {}", commit.id))?;
+                if let Some(environment) = &environment {
+                    if config.has_environment(environment) {
+                        config.select_environment(Some(environment.clone()))?;
+                    }
+                }
+                Some(config)
+            }
+        };
+        configs.push(config);
+    }
+    // Before the first commit
+    configs.push(None);
+
+    let secret_key = match configs.iter().flatten().next() {
+        Some(config) if key_source.is_provided() => Some(config.load_secret_key(&key_source)?),
+        _ => None,
+    };
+    // Older versions may not be encrypted for the key yet
+    let decrypt = |config: &config::Config| -> Result<Option<Vec<u8>>> {
+        match &secret_key {
+            Some(secret_key) if config.accepts(secret_key) => {
+                config.get_secret(&key, secret_key).map(Some)
+            }
+            _ => Ok(None),
+        }
+    };
+
+    let mut found = false;
+    for (commit, versions) in history.iter().zip(configs.windows(2)) {
+        let [new, old] = [&versions[0], &versions[1]].map(|config| {
+            config
+                .as_ref()
+                .filter(|config| config.value_kind(&key).is_some())
+        });
+        let change = match (old, new) {
+            (None, None) => continue,
+            (None, Some(_)) => "added",
+            (Some(_), None) => "removed",
+            (Some(old), Some(new)) => {
+                let same = match old.same_value(new, &key) {
+                    Some(same) => Some(same),
+                    None => match (decrypt(old)?, decrypt(new)?) {
+                        (Some(old), Some(new)) => Some(old == new),
+                        _ => None,
+                    },
+                };
+                match same {
+                    Some(true) => continue,
+                    Some(false) => "changed",
+                    None => "possibly changed",
+                }
+            }
+        };
+        found = true;
+        println!(
+            "{} {} {} by {}: {}",
+            &commit.id[..commit.id.len().min(10)],
+            commit.date,
+            change,
+            commit.author,
+            commit.subject
+        );
+        if let Some(new) = new.filter(|_| show_values) {
+            match decrypt(new)? {
+                None => println!("    value: unable to decrypt"),
+                Some(value) => match new.value_kind(&key) {
+                    Some(config::ValueKind::Binary) => println!(
+                        "    value (base64): {}",
+                        base64::engine::general_purpose::STANDARD.encode(value)
+                    ),
+                    _ => println!("    value: {}", String::from_utf8(value)?),
+                },
+            }
+        }
+    }
+    if !found {
+        eprintln!(
+            "{} was never set in the history of {}",
+            key,
+            amber_yaml.display()
+        );
+    }
+    Ok(())
+}
+
+/// One of the amber files compared by [diff]
+struct DiffSide {
+    /// Path, or REV:PATH
+    spec: String,
+    environment: Option<String>,
+    secret_key_file: Option<PathBuf>,
+}
+
+impl DiffSide {
+    fn new(spec: String, environment: Option<String>, secret_key_file: Option<PathBuf>) -> Self {
+        DiffSide {
+            spec,
+            environment,
+            secret_key_file,
+        }
+    }
+
+    fn load(&self, opt: &mut cli::Opt) -> Result<(config::Config, Option<SecretKey>)> {
+        let mut config = match self.spec.split_once(':') {
+            Some((rev, path)) if !Path::new(&self.spec).exists() => {
+                let revision = git::Revision::new(rev)?;
+                let path = if path.is_empty() {
+                    opt.find_amber_yaml_in(&revision)?.to_owned()
+                } else {
+                    path.into()
+                };
+                config::Config::load_from(&revision, &path)?
+            }
+            _ => config::Config::load(&self.spec)?,
+        };
+        config.select_environment(self.environment.clone())?;
+        let secret_key = match &self.secret_key_file {
+            Some(file) => {
+                let key_source = key_source::KeySource {
+                    file: Some(file.clone()),
+                    plugin: None,
+                };
+                let secret_key = config
+                    .load_secret_key(&key_source)
+                    .with_context(|| format!("Unable to decrypt {}", self))?;
+                Some(secret_key)
+            }
+            None => {
+                let key_source = opt.key_source();
+                if key_source.is_provided() {
+                    // The usual secret key may only belong to the other side
+                    config
+                        .load_secret_key(&key_source)
+                        .and_then(|secret_key| {
+                            ensure!(
+                                config.accepts(&secret_key),
+                                "The secret key can't decrypt these secrets"
+                            );
+                            Ok(secret_key)
+                        })
+                        .map_err(|e| log::debug!("Comparing {self} without a secret key: {e:#}"))
+                        .ok()
+                } else {
+                    None
+                }
+            }
+        };
+        Ok((config, secret_key))
+    }
+}
+
+impl std::fmt::Display for DiffSide {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.environment {
+            None => f.write_str(&self.spec),
+            Some(environment) => write!(f, "{} ({})", self.spec, environment),
+        }
+    }
+}
+
+fn diff(mut opt: cli::Opt, left: DiffSide, right: DiffSide, json: bool) -> Result<()> {
+    #[derive(Serialize, Clone, Copy, PartialEq)]
+    #[serde(rename_all = "snake_case")]
+    enum Status {
+        OnlyLeft,
+        OnlyRight,
+        Same,
+        Different,
+        /// Values couldn't be compared without secret keys
+        Unknown,
+    }
+    #[derive(Serialize)]
+    struct Entry<'a> {
+        name: &'a str,
+        status: Status,
+    }
+
+    // Sides are read from git revisions with REV:PATH instead
+    reject_rev(&opt)?;
+    let (left_config, left_key) = left.load(&mut opt)?;
+    let (right_config, right_key) = right.load(&mut opt)?;
+    let names = |config: &config::Config| -> BTreeSet<String> {
+        config
+            .metadata()
+            .into_iter()
+            .map(|(name, _)| name.clone())
+            .collect()
+    };
+    let (left_names, right_names) = (names(&left_config), names(&right_config));
+    let mut entries = Vec::new();
+    for name in left_names.union(&right_names) {
+        let status = match (left_names.contains(name), right_names.contains(name)) {
+            (true, false) => Status::OnlyLeft,
+            (false, true) => Status::OnlyRight,
+            _ => match (&left_key, &right_key) {
+                (Some(left_key), Some(right_key)) => {
+                    let left_value = left_config.get_secret(name, left_key)?;
+                    if left_value == right_config.get_secret(name, right_key)? {
+                        Status::Same
+                    } else {
+                        Status::Different
+                    }
+                }
+                _ => match left_config.same_value(&right_config, name) {
+                    Some(true) => Status::Same,
+                    Some(false) => Status::Different,
+                    None => Status::Unknown,
+                },
+            },
+        };
+        entries.push(Entry { name, status });
+    }
+
+    if json {
+        serde_json::to_writer(std::io::stdout(), &entries)?;
+        return Ok(());
+    }
+    for entry in &entries {
+        match entry.status {
+            Status::OnlyLeft => println!("{} only in {}", entry.name, left),
+            Status::OnlyRight => println!("{} only in {}", entry.name, right),
+            Status::Different => println!("{} differs", entry.name),
+            Status::Unknown => println!("{} may differ", entry.name),
+            Status::Same => {}
+        }
+    }
+    if entries.iter().any(|entry| entry.status == Status::Unknown) {
+        eprintln!("Provide secret keys for both files to compare all values");
+    }
+    Ok(())
+}
+
+fn recipient(mut opt: cli::Opt, sub: cli::RecipientCommand) -> Result<()> {
+    let key_source = opt.key_source();
+    let (amber_yaml, mut config) = load_config(&mut opt)?;
+    match sub {
+        cli::RecipientCommand::List => {
+            for (name, public_key) in config.recipients() {
+                println!("{name} {}", hex::encode(public_key));
+            }
+            return Ok(());
+        }
+        cli::RecipientCommand::Add {
+            name,
+            public_key,
+            only_secret_key,
+        } => {
+            let secret_key = config.load_secret_key(&key_source)?;
+            let (public_key, new_secret_key) = new_public_key(public_key)?;
+            let names = config.add_recipient(name.clone(), public_key, &secret_key)?;
+            config.save(&amber_yaml)?;
+            report_reencrypted(&names);
+            eprintln!("Added recipient {name}");
+            if let Some(new_secret_key) = new_secret_key {
+                print_secret_key(&hex::encode(new_secret_key.to_bytes()), only_secret_key);
+            }
+        }
+        cli::RecipientCommand::Remove { name } => {
+            let secret_key = config.load_secret_key(&key_source)?;
+            let names = config.remove_recipient(&name, &secret_key)?;
+            config.save(&amber_yaml)?;
+            report_reencrypted(&names);
+            eprintln!("Removed recipient {name}");
+            eprintln!(
+                "Warning: older revisions of {} (e.g. in your Git history) can still be decrypted with {name}'s key.",
+                amber_yaml.display()
+            );
+            eprintln!(
+                "Rotate any secret values {name} had access to if that key may be compromised."
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Parse the given public key, or generate a new keypair if there is none
+fn new_public_key(public_key: Option<String>) -> Result<(PublicKey, Option<SecretKey>)> {
+    Ok(match public_key {
+        Some(public_key) => (config::parse_public_key(&public_key)?, None),
+        None => {
+            let new_secret_key = SecretKey::generate(&mut OsRng);
+            (new_secret_key.public_key(), Some(new_secret_key))
+        }
+    })
+}
+
+fn environment(mut opt: cli::Opt, sub: cli::EnvCommand) -> Result<()> {
+    let key_source = opt.key_source();
+    let (amber_yaml, mut config) = load_config(&mut opt)?;
+    match sub {
+        cli::EnvCommand::List => {
+            for (name, recipients) in config.environments() {
+                let recipients: Vec<_> = recipients.iter().map(String::as_str).collect();
+                println!("{name} {}", recipients.join(","));
+            }
+        }
+        cli::EnvCommand::Add {
+            name,
+            recipient,
+            public_key,
+            only_secret_key,
+        } => {
+            let secret_key = config.load_secret_key(&key_source)?;
+            let (public_key, new_secret_key) = new_public_key(public_key)?;
+            let recipient = recipient.unwrap_or_else(|| name.clone());
+            let names =
+                config.add_environment(name.clone(), recipient.clone(), public_key, &secret_key)?;
+            config.save(&amber_yaml)?;
+            report_reencrypted(&names);
+            eprintln!("Added environment {name} with recipient {recipient}");
+            if let Some(new_secret_key) = new_secret_key {
+                print_secret_key(&hex::encode(new_secret_key.to_bytes()), only_secret_key);
+            }
+        }
+        cli::EnvCommand::Remove { name } => {
+            let secret_key = config.load_secret_key(&key_source)?;
+            let names = config.remove_environment(&name, &secret_key)?;
+            config.save(&amber_yaml)?;
+            report_reencrypted(&names);
+            eprintln!("Removed environment {name}");
+            eprintln!(
+                "Warning: older revisions of {} (e.g. in your Git history) can still be decrypted with the keys of {name}'s recipients.",
+                amber_yaml.display()
+            );
+        }
+    }
+    Ok(())
+}
+
+fn report_reencrypted(names: &[String]) {
+    for name in names {
+        eprintln!("Re-encrypted {name}");
+    }
+}
@@ -0,0 +1,167 @@
+//! Secret key files encrypted under a passphrase.
+//!
+//! The passphrase is stretched with Argon2id into a key for XChaCha20-Poly1305, which
+//! encrypts the raw secret key bytes. The result is stored as a small YAML document.
+
+use std::convert::TryInto;
+use std::io::Write;
+use std::path::Path;
+
+use anyhow::*;
+use argon2::{Algorithm, Argon2, Params, Version};
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, Payload};
+use chacha20poly1305::XChaCha20Poly1305;
+use crypto_box::aead::rand_core::RngCore;
+use crypto_box::aead::OsRng;
+use crypto_box::SecretKey;
+use serde::{Deserialize, Serialize};
+
+/// Environment variable providing the passphrase non-interactively, e.g. for tests
+pub const PASSPHRASE_ENV: &str = "AMBER_PASSPHRASE";
+
+/// Identifies a passphrase protected key file, also authenticated as associated data
+const KIND: &str = "amber-passphrase-secret-key-v1";
+
+/// On disk representation of a passphrase protected secret key
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct EncryptedKeyRaw {
+    /// Always [KIND]
+    kind: String,
+    /// Argon2id memory cost in KiB
+    m_cost: u32,
+    /// Argon2id number of iterations
+    t_cost: u32,
+    /// Argon2id degree of parallelism
+    p_cost: u32,
+    /// Hex encoded Argon2id salt
+    salt: String,
+    /// Hex encoded XChaCha20-Poly1305 nonce
+    nonce: String,
+    /// Hex encoded encrypted secret key
+    cipher: String,
+}
+
+/// Does this key file content look like a passphrase protected key?
+pub fn is_encrypted(contents: &str) -> bool {
+    contents.contains(KIND)
+}
+
+/// Encrypt a secret key under the given passphrase, returning the file contents
+fn encrypt(secret_key: &SecretKey, passphrase: &str) -> Result<String> {
+    let params = Params::default();
+    let mut salt = [0u8; 16];
+    OsRng.fill_bytes(&mut salt);
+    let cipher = make_cipher(passphrase, &salt, params.clone())?;
+    let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let encrypted = cipher
+        .encrypt(
+            &nonce,
+            Payload {
+                msg: &secret_key.to_bytes(),
+                aad: KIND.as_bytes(),
+            },
+        )
+        .map_err(|_| anyhow!("Error during encryption"))?;
+    let raw = EncryptedKeyRaw {
+        kind: KIND.to_owned(),
+        m_cost: params.m_cost(),
+        t_cost: params.t_cost(),
+        p_cost: params.p_cost(),
+        salt: hex::encode(salt),
+        nonce: hex::encode(nonce),
+        cipher: hex::encode(encrypted),
+    };
+    serde_yaml::to_string(&raw).map_err(Error::new)
+}
+
+/// Decrypt the contents of a passphrase protected key file
+pub fn decrypt(contents: &str, passphrase: &str) -> Result<SecretKey> {
+    let raw: EncryptedKeyRaw =
+        serde_yaml::from_str(contents).context("Invalid passphrase protected key file")?;
+    ensure!(raw.kind == KIND, "Unsupported key file kind {}", raw.kind);
+    let params = Params::new(raw.m_cost, raw.t_cost, raw.p_cost, None)
+        .map_err(|e| anyhow!("Invalid Argon2 parameters: {e}"))?;
+    let salt = hex::decode(&raw.salt).ok().context("Non-hex salt")?;
+    let nonce: [u8; 24] = hex::decode(&raw.nonce)
+        .ok()
+        .context("Non-hex nonce")?
+        .try_into()
+        .map_err(|_| anyhow!("Invalid nonce"))?;
+    let encrypted = hex::decode(&raw.cipher).ok().context("Non-hex cipher")?;
+    let cipher = make_cipher(passphrase, &salt, params)?;
+    let plain = cipher
+        .decrypt(
+            &nonce.into(),
+            Payload {
+                msg: &encrypted,
+                aad: KIND.as_bytes(),
+            },
+        )
+        .map_err(|_| anyhow!("Wrong passphrase, or the key file was modified"))?;
+    let bs: [u8; 32] = plain
+        .try_into()
+        .map_err(|_| anyhow!("Invalid secret key, expected 32 bytes"))?;
+    Ok(SecretKey::from(bs))
+}
+
+fn make_cipher(passphrase: &str, salt: &[u8], params: Params) -> Result<XChaCha20Poly1305> {
+    let mut key = [0u8; 32];
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|e| anyhow!("Unable to derive key from passphrase: {e}"))?;
+    Ok(XChaCha20Poly1305::new(&key.into()))
+}
+
+/// Write a new passphrase protected key file, refusing to overwrite an existing file
+pub fn write_key_file(path: &Path, secret_key: &SecretKey, passphrase: &str) -> Result<()> {
+    (|| {
+        let contents = encrypt(secret_key, passphrase)?;
+        let mut options = fs_err::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        {
+            use fs_err::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        let mut file = options.open(path)?;
+        file.write_all(contents.as_bytes())?;
+        file.sync_all()?;
+        Ok(())
+    })()
+    .with_context(|| format!("Unable to write secret key file {}", path.display()))
+}
+
+/// Get the passphrase for an existing key file, prompting on the terminal
+pub fn read_passphrase(path: &Path) -> Result<String> {
+    if let Some(passphrase) = std::env::var_os(PASSPHRASE_ENV) {
+        return passphrase
+            .into_string()
+            .ok()
+            .with_context(|| format!("Invalid UTF-8 encoding in {PASSPHRASE_ENV}"));
+    }
+    rpassword::prompt_password(format!("Passphrase for {}: ", path.display()))
+        .context("Unable to read passphrase from the terminal")
+}
+
+/// Get a passphrase for a new key file, prompting twice on the terminal
+pub fn read_new_passphrase() -> Result<String> {
+    if let Some(passphrase) = std::env::var_os(PASSPHRASE_ENV) {
+        let passphrase = passphrase
+            .into_string()
+            .ok()
+            .with_context(|| format!("Invalid UTF-8 encoding in {PASSPHRASE_ENV}"))?;
+        ensure!(!passphrase.is_empty(), "Passphrase must not be empty");
+        return Ok(passphrase);
+    }
+    let read = |prompt: &str| {
+        rpassword::prompt_password(prompt).context("Unable to read passphrase from the terminal")
+    };
+    let passphrase = read("New passphrase: ")?;
+    ensure!(!passphrase.is_empty(), "Passphrase must not be empty");
+    ensure!(
+        passphrase == read("Confirm passphrase: ")?,
+        "Passphrases do not match"
+    );
+    Ok(passphrase)
+}
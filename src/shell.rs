@@ -0,0 +1,58 @@
+//! Commands setting environment variables in various shells, for `amber print`.
+//!
+//! Values are quoted so that the shell takes them literally: nothing in them is expanded.
+
+use anyhow::*;
+
+/// `export NAME='value'` for POSIX shells, such as sh, bash, dash and zsh
+pub fn posix(name: &str, value: &str) -> String {
+    // Nothing is special within single quotes, which can't contain single quotes themselves
+    format!("export {name}='{}'", value.replace('\'', r"'\''"))
+}
+
+/// `set -gx NAME 'value'` for fish
+pub fn fish(name: &str, value: &str) -> String {
+    // Backslashes and single quotes are the only escapes within single quotes
+    let value = value.replace('\\', r"\\").replace('\'', r"\'");
+    format!("set -gx {name} '{value}'")
+}
+
+/// `$env:NAME = 'value'` for PowerShell
+pub fn powershell(name: &str, value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len());
+    for c in value.chars() {
+        // PowerShell also accepts typographic single quotes, which are doubled the same way
+        if matches!(c, '\'' | '\u{2018}' | '\u{2019}' | '\u{201a}' | '\u{201b}') {
+            quoted.push(c);
+        }
+        quoted.push(c);
+    }
+    format!("$env:{name} = '{quoted}'")
+}
+
+/// `$env.NAME = r#'value'#` for nushell
+pub fn nushell(name: &str, value: &str) -> String {
+    // Raw strings end at a single quote followed by as many hashes as they started with
+    let hashes = (1..)
+        .map(|count| "#".repeat(count))
+        .find(|hashes| !value.contains(&format!("'{hashes}")))
+        .expect("Some number of hashes is not in the value");
+    format!("$env.{name} = r{hashes}'{value}'{hashes}")
+}
+
+/// `set "NAME=value"` for cmd.exe batch files
+///
+/// Fails for values with line breaks or double quotes, which cmd can't represent safely.
+pub fn cmd(name: &str, value: &str) -> Result<String> {
+    ensure!(
+        !value.contains(['\n', '\r']),
+        "The value of {name} contains a line break, which cmd can't set"
+    );
+    // Within quotes, & | < > ^ are taken literally, but a double quote would end the quoted part,
+    // letting the rest run as commands. Variables are expanded regardless, so % is doubled.
+    ensure!(
+        !value.contains('"'),
+        "The value of {name} contains a double quote, which cmd can't set safely"
+    );
+    Ok(format!("set \"{name}={}\"", value.replace('%', "%%")))
+}
@@ -0,0 +1,104 @@
+mod common;
+
+use common::{amber_with_key, AMBER_YAML, KEYTAB, KEYTAB_BASE64, SECRET_KEY};
+
+#[test]
+fn binary_round_trip() {
+    let dir = tempfile::tempdir().unwrap();
+    let amber_yaml = dir.path().join("amber.yaml");
+    std::fs::copy(AMBER_YAML, &amber_yaml).unwrap();
+    let keytab = dir.path().join("krb5.keytab");
+    std::fs::write(&keytab, KEYTAB).unwrap();
+
+    let output = amber_with_key(&amber_yaml, SECRET_KEY)
+        .args(["encrypt", "KEYTAB", "--file"])
+        .arg(&keytab)
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8(output.stderr)
+        .unwrap()
+        .contains("--binary"));
+
+    // Older file format versions can't hold binary values
+    let output = amber_with_key(&amber_yaml, SECRET_KEY)
+        .args(["encrypt", "KEYTAB", "--binary", "--file"])
+        .arg(&keytab)
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8(output.stderr)
+        .unwrap()
+        .contains("Run amber migrate"));
+    let status = amber_with_key(&amber_yaml, SECRET_KEY)
+        .arg("migrate")
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    let status = amber_with_key(&amber_yaml, SECRET_KEY)
+        .args(["encrypt", "KEYTAB", "--binary", "--file"])
+        .arg(&keytab)
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    let dest = dir.path().join("out.keytab");
+    let status = amber_with_key(&amber_yaml, SECRET_KEY)
+        .args(["write-file", "--key", "KEYTAB", "--dest"])
+        .arg(&dest)
+        .status()
+        .unwrap();
+    assert!(status.success());
+    assert_eq!(std::fs::read(&dest).unwrap(), KEYTAB);
+
+    let output = amber_with_key(&amber_yaml, SECRET_KEY)
+        .args(["print", "--style", "json"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let secrets: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let keytab = secrets
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|secret| secret["key"] == "KEYTAB")
+        .unwrap();
+    assert_eq!(keytab["value"], KEYTAB_BASE64);
+    assert_eq!(keytab["encoding"], "base64");
+    assert!(secrets[0].get("encoding").is_none());
+
+    let output = amber_with_key(&amber_yaml, SECRET_KEY)
+        .args([
+            "--unmasked",
+            "exec",
+            "sh",
+            "--",
+            "-c",
+            "printf %s \"$KEYTAB\"",
+        ])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(output.stdout, KEYTAB_BASE64.as_bytes());
+
+    let output = amber_with_key(&amber_yaml, SECRET_KEY)
+        .args(["list", "--json"])
+        .output()
+        .unwrap();
+    let list: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(list[3]["kind"], "binary");
+
+    // Storing a text value again switches the kind back
+    let status = amber_with_key(&amber_yaml, SECRET_KEY)
+        .args(["encrypt", "KEYTAB", "text"])
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let output = amber_with_key(&amber_yaml, SECRET_KEY)
+        .args(["list", "--json"])
+        .output()
+        .unwrap();
+    let list: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert!(list[3].get("kind").is_none());
+}
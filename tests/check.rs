@@ -0,0 +1,102 @@
+mod common;
+
+use common::{amber_file, temp_copy, AMBER_YAML, SECRET_KEY};
+use std::path::Path;
+use std::process::Output;
+
+fn check(amber_yaml: &Path, args: &[&str]) -> Output {
+    amber_file(amber_yaml)
+        .arg("check")
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+fn stdout(output: &Output) -> &str {
+    std::str::from_utf8(&output.stdout).unwrap()
+}
+
+fn days_from_now(days: i64) -> String {
+    (chrono::Utc::now() + chrono::Duration::days(days))
+        .format("%Y-%m-%d")
+        .to_string()
+}
+
+#[test]
+fn expiry_and_rotation_deadlines() {
+    let temp = temp_copy(AMBER_YAML);
+    // Metadata needs the latest file format version
+    let status = amber_file(&temp)
+        .arg("migrate")
+        .env("AMBER_SECRET", SECRET_KEY)
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    let output = check(&temp, &[]);
+    assert!(output.status.success());
+    assert_eq!(stdout(&output), "");
+
+    let status = amber_file(&temp)
+        .args(["encrypt", "API_TOKEN", "token"])
+        .args(["--expires-at", &days_from_now(10)])
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    // Soon to expire secrets are reported, but only fail past the threshold
+    let output = check(&temp, &[]);
+    assert!(output.status.success());
+    assert!(stdout(&output).starts_with("API_TOKEN expires on "));
+    let output = check(&temp, &["--warn-within", "1w"]);
+    assert!(output.status.success());
+    assert_eq!(stdout(&output), "");
+    let output = check(&temp, &["--fail-within", "2w"]);
+    assert!(!output.status.success());
+
+    let status = amber_file(&temp)
+        .args(["encrypt", "API_TOKEN", "token"])
+        .args(["--expires-at", &days_from_now(-1)])
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let output = check(&temp, &[]);
+    assert!(!output.status.success());
+    assert!(stdout(&output).starts_with("API_TOKEN expired on "));
+
+    // Rotation deadlines are relative to the last update
+    let status = amber_file(&temp)
+        .args(["encrypt", "API_TOKEN", "token", "--expires-at", ""])
+        .args(["--rotate-after", "2w"])
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let output = check(&temp, &[]);
+    assert!(output.status.success());
+    assert!(stdout(&output).starts_with("API_TOKEN is due for rotation on "));
+    let output = check(&temp, &["--fail-within", "15d"]);
+    assert!(!output.status.success());
+
+    for period in ["soon", "9日", "é", "d"] {
+        let output = amber_file(&temp)
+            .args(["encrypt", "API_TOKEN", "token", "--rotate-after", period])
+            .output()
+            .unwrap();
+        assert_eq!(output.status.code(), Some(1), "{period}");
+        let output = check(&temp, &["--warn-within", period]);
+        assert_eq!(output.status.code(), Some(2), "{period}");
+    }
+
+    // Periods too long to compute a date for never end
+    let status = amber_file(&temp)
+        .args(["encrypt", "API_TOKEN", "token"])
+        .args(["--rotate-after", "4294967295w"])
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let output = check(&temp, &[]);
+    assert!(output.status.success());
+    assert_eq!(stdout(&output), "");
+    let output = check(&temp, &["--fail-within", "4294967295w"]);
+    assert!(output.status.success());
+}
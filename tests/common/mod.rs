@@ -0,0 +1,135 @@
+//! Helpers shared by the integration tests
+
+// Each test crate only uses some of the helpers
+#![allow(dead_code)]
+
+use assert_cmd::prelude::*;
+use std::path::Path;
+use std::process::{Command, Output};
+
+/// The test file most tests start from, a version 1 file, and its secret key
+pub const AMBER_YAML: &str = "assets/amber-masking.yaml";
+pub const SECRET_KEY: &str = "ac2af4852f3de2dc6feb19b718d1cbf6c64c1ef618dafaf2b0a89cadcde240ac";
+
+/// A binary value, which isn't valid UTF-8, and its base64 encoding
+pub const KEYTAB: &[u8] = b"\x05\x02\x00\x00\x00\x41\xff\xfe\x80binary\x00";
+pub const KEYTAB_BASE64: &str = "BQIAAABB//6AYmluYXJ5AA==";
+
+/// The amber binary, unaffected by any `AMBER_*` variables of the environment running the tests
+pub fn amber() -> Command {
+    without_amber_vars(Command::cargo_bin("amber").unwrap())
+}
+
+/// Keep the `AMBER_*` variables of the environment running the tests from reaching the command
+pub fn without_amber_vars(mut cmd: Command) -> Command {
+    for (name, _) in std::env::vars_os() {
+        if name.to_string_lossy().starts_with("AMBER_") {
+            cmd.env_remove(name);
+        }
+    }
+    cmd
+}
+
+/// The amber binary working on the given amber file
+pub fn amber_file(amber_yaml: &Path) -> Command {
+    let mut cmd = amber();
+    cmd.env("AMBER_YAML", amber_yaml);
+    cmd
+}
+
+/// The amber binary working on the given amber file, with the given secret key
+pub fn amber_with_key(amber_yaml: &Path, secret_key: &str) -> Command {
+    let mut cmd = amber_file(amber_yaml);
+    cmd.env("AMBER_SECRET", secret_key);
+    cmd
+}
+
+/// The amber binary run in the given directory, finding the amber file from there
+pub fn amber_in(dir: &Path) -> Command {
+    let mut cmd = amber();
+    cmd.current_dir(dir);
+    cmd
+}
+
+/// Run amber on the given amber file, with a secret key if given
+pub fn run_amber(amber_yaml: &Path, secret_key: Option<&str>, args: &[&str]) -> Output {
+    let mut cmd = amber_file(amber_yaml);
+    cmd.args(args);
+    if let Some(secret_key) = secret_key {
+        cmd.env("AMBER_SECRET", secret_key);
+    }
+    cmd.output().unwrap()
+}
+
+/// Assert that the command succeeded, returning its standard output
+pub fn success(output: Output) -> String {
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(output.status.success(), "{}", stderr);
+    String::from_utf8(output.stdout).unwrap()
+}
+
+/// Assert that the command failed, returning its standard error
+pub fn failure(output: Output) -> String {
+    assert!(!output.status.success());
+    String::from_utf8(output.stderr).unwrap()
+}
+
+/// The standard error of the command
+pub fn stderr(output: &Output) -> &str {
+    std::str::from_utf8(&output.stderr).unwrap()
+}
+
+/// Write a key file with the given permissions, which only exist on Unix
+pub fn write_key(path: &Path, contents: &str, mode: u32) {
+    std::fs::write(path, contents).unwrap();
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode)).unwrap();
+    }
+    #[cfg(not(unix))]
+    let _ = mode;
+}
+
+/// Run git in the given directory, as a fixed author, returning its output
+pub fn git(dir: &Path, args: &[&str]) -> String {
+    success(
+        Command::new("git")
+            .current_dir(dir)
+            .args(args)
+            .env("GIT_AUTHOR_NAME", "Jane Doe")
+            .env("GIT_AUTHOR_EMAIL", "jane@example.com")
+            .env("GIT_AUTHOR_DATE", "2025-06-30T12:00:00Z")
+            .env("GIT_COMMITTER_NAME", "Jane Doe")
+            .env("GIT_COMMITTER_EMAIL", "jane@example.com")
+            .output()
+            .unwrap(),
+    )
+}
+
+/// A copy of an amber file, for tests changing it
+pub fn temp_copy(amber_yaml: &str) -> tempfile::TempPath {
+    let path = tempfile::NamedTempFile::new().unwrap().into_temp_path();
+    std::fs::copy(amber_yaml, &path).unwrap();
+    path
+}
+
+/// A secret printed with `--style json`
+#[derive(serde::Deserialize, PartialEq, Eq, Debug)]
+pub struct Pair {
+    pub key: String,
+    pub value: String,
+}
+
+/// The secrets of an amber file, decrypted with the given secret key
+pub fn get_vars(amber_yaml: &Path, secret_key: &str) -> Vec<Pair> {
+    let output = amber()
+        .arg("print")
+        .arg("--style")
+        .arg("json")
+        .env("AMBER_YAML", amber_yaml)
+        .env("AMBER_SECRET", secret_key)
+        .output()
+        .unwrap();
+    serde_json::from_str(&success(output)).unwrap()
+}
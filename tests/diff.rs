@@ -0,0 +1,146 @@
+mod common;
+
+use common::{amber_in, git, success, write_key};
+use std::path::Path;
+
+/// Create an amber file with the given secrets, returning its secret key
+fn create(dir: &Path, name: &str, secrets: &[(&str, &str)]) -> String {
+    let secret_key = success(
+        amber_in(dir)
+            .env("AMBER_YAML", name)
+            .args(["init", "--only-secret-key"])
+            .output()
+            .unwrap(),
+    );
+    for (key, value) in secrets {
+        success(
+            amber_in(dir)
+                .env("AMBER_YAML", name)
+                .env("AMBER_SECRET", &secret_key)
+                .args(["encrypt", key, value])
+                .output()
+                .unwrap(),
+        );
+    }
+    secret_key
+}
+
+#[test]
+fn diff_files() {
+    let dir = tempfile::tempdir().unwrap();
+    let dir = dir.path();
+    let staging_key = create(
+        dir,
+        "staging.yaml",
+        &[
+            ("API_URL", "https://api"),
+            ("DB_URL", "staging-db"),
+            ("DEBUG", "1"),
+        ],
+    );
+    let prod_key = create(
+        dir,
+        "prod.yaml",
+        &[
+            ("API_URL", "https://api"),
+            ("DB_URL", "prod-db"),
+            ("SENTRY", "dsn"),
+        ],
+    );
+
+    let output = success(
+        amber_in(dir)
+            .args(["diff", "staging.yaml", "prod.yaml"])
+            .output()
+            .unwrap(),
+    );
+    assert_eq!(
+        output,
+        "API_URL may differ\nDB_URL may differ\nDEBUG only in staging.yaml\nSENTRY only in prod.yaml\n"
+    );
+
+    write_key(&dir.join("staging.key"), &staging_key, 0o600);
+    write_key(&dir.join("prod.key"), &prod_key, 0o600);
+    let output = success(
+        amber_in(dir)
+            .args(["diff", "staging.yaml", "prod.yaml", "--json"])
+            .args(["--left-secret-key-file", "staging.key"])
+            .args(["--right-secret-key-file", "prod.key"])
+            .output()
+            .unwrap(),
+    );
+    let entries: serde_json::Value = serde_json::from_str(&output).unwrap();
+    assert_eq!(
+        entries,
+        serde_json::json!([
+            {"name": "API_URL", "status": "same"},
+            {"name": "DB_URL", "status": "different"},
+            {"name": "DEBUG", "status": "only_left"},
+            {"name": "SENTRY", "status": "only_right"},
+        ])
+    );
+
+    // The usual key only decrypts the files it belongs to
+    let output = success(
+        amber_in(dir)
+            .args(["diff", "staging.yaml", "prod.yaml"])
+            .env("AMBER_SECRET", &staging_key)
+            .output()
+            .unwrap(),
+    );
+    assert_eq!(
+        output,
+        "API_URL may differ\nDB_URL may differ\nDEBUG only in staging.yaml\nSENTRY only in prod.yaml\n"
+    );
+    let output = success(
+        amber_in(dir)
+            .args(["diff", "staging.yaml", "prod.yaml"])
+            .args(["--right-secret-key-file", "prod.key"])
+            .env("AMBER_SECRET", &staging_key)
+            .output()
+            .unwrap(),
+    );
+    assert_eq!(
+        output,
+        "DB_URL differs\nDEBUG only in staging.yaml\nSENTRY only in prod.yaml\n"
+    );
+
+    // An explicitly given key not belonging to its file fails
+    let output = amber_in(dir)
+        .args(["diff", "staging.yaml", "prod.yaml"])
+        .args(["--right-secret-key-file", "staging.key"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+}
+
+#[test]
+fn diff_revisions() {
+    let dir = tempfile::tempdir().unwrap();
+    let dir = dir.path();
+    git(dir, &["init", "--quiet"]);
+    let secret_key = create(dir, "amber.yaml", &[("API_URL", "api"), ("DB_URL", "db")]);
+    git(dir, &["add", "-A"]);
+    git(dir, &["commit", "--quiet", "-m", "first"]);
+    for args in [
+        ["encrypt", "DB_URL", "new-db"],
+        ["encrypt", "SENTRY", "dsn"],
+    ] {
+        success(
+            amber_in(dir)
+                .env("AMBER_SECRET", &secret_key)
+                .args(args)
+                .output()
+                .unwrap(),
+        );
+    }
+
+    // Versions of the same file can be compared without a secret key
+    let output = success(
+        amber_in(dir)
+            .args(["diff", "HEAD:", "amber.yaml"])
+            .output()
+            .unwrap(),
+    );
+    assert_eq!(output, "DB_URL differs\nSENTRY only in amber.yaml\n");
+}
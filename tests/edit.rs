@@ -0,0 +1,196 @@
+mod common;
+
+use common::{amber_with_key, success, AMBER_YAML, SECRET_KEY};
+use std::path::Path;
+use std::process::{Output, Stdio};
+
+/// Run amber with an editor recording the file it's given, then replacing it with `edited`
+fn edit(dir: &Path, amber_yaml: &Path, secret_key: &str, args: &[&str], edited: &str) -> Output {
+    let script = dir.join("editor.sh");
+    std::fs::write(
+        &script,
+        "cp \"$1\" \"$EDIT_DIR/seen\"\nls -l \"$1\" | cut -c1-10 > \"$EDIT_DIR/mode\"\necho \"$1\" > \"$EDIT_DIR/path\"\ncat \"$EDIT_DIR/edited\" > \"$1\"\n",
+    )
+    .unwrap();
+    std::fs::write(dir.join("edited"), edited).unwrap();
+    amber_with_key(amber_yaml, secret_key)
+        .env("EDIT_DIR", dir)
+        .env_remove("VISUAL")
+        .env("EDITOR", format!("sh {}", script.display()))
+        .arg("edit")
+        .args(args)
+        .stdin(Stdio::null())
+        .output()
+        .unwrap()
+}
+
+/// The file rendered by textconv
+fn textconv(amber_yaml: &Path, args: &[&str]) -> String {
+    success(
+        amber_with_key(amber_yaml, SECRET_KEY)
+            .arg("textconv")
+            .args(args)
+            .arg(amber_yaml)
+            .output()
+            .unwrap(),
+    )
+}
+
+/// The masked rendering of a secret, which changes whenever it's encrypted again
+fn masked(amber_yaml: &Path, name: &str) -> String {
+    let rendered = textconv(amber_yaml, &["--values", "masked"]);
+    let prefix = format!("{name}=");
+    rendered
+        .lines()
+        .find(|line| line.starts_with(&prefix))
+        .unwrap()
+        .to_owned()
+}
+
+#[test]
+fn edit_applies_changes() {
+    let dir = tempfile::tempdir().unwrap();
+    let amber_yaml = dir.path().join("amber.yaml");
+    std::fs::copy(AMBER_YAML, &amber_yaml).unwrap();
+    let bar = masked(&amber_yaml, "BAR");
+
+    let output = edit(
+        dir.path(),
+        &amber_yaml,
+        SECRET_KEY,
+        &[],
+        "# BAZ is removed\nBAR=SECRET123\nFOO=changed # comment\nexport MULTILINE=\"first\\nsecond \\\"quoted\\\"\"\n",
+    );
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let seen = std::fs::read_to_string(dir.path().join("seen")).unwrap();
+    assert!(
+        seen.ends_with("\nBAR=SECRET123\nBAZ=something\nFOO=secret1\n"),
+        "{}",
+        seen
+    );
+    assert_eq!(
+        std::fs::read_to_string(dir.path().join("mode")).unwrap(),
+        "-rw-------\n"
+    );
+    let path = std::fs::read_to_string(dir.path().join("path")).unwrap();
+    assert!(!Path::new(path.trim()).exists());
+
+    assert_eq!(
+        textconv(&amber_yaml, &[]),
+        "# recipients: default\nBAR=SECRET123\nFOO=changed\nMULTILINE=first\\nsecond \"quoted\"\n"
+    );
+    // Unchanged values are not encrypted again
+    assert_eq!(masked(&amber_yaml, "BAR"), bar);
+}
+
+#[test]
+fn edit_yaml_rejects_invalid_names() {
+    let dir = tempfile::tempdir().unwrap();
+    let amber_yaml = dir.path().join("amber.yaml");
+    std::fs::copy(AMBER_YAML, &amber_yaml).unwrap();
+    let original = std::fs::read(&amber_yaml).unwrap();
+
+    // Without a terminal to ask whether to edit again, nothing is changed
+    let output = edit(
+        dir.path(),
+        &amber_yaml,
+        SECRET_KEY,
+        &["--format", "yaml"],
+        "lower: value\n",
+    );
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Invalid secret name lower"), "{}", stderr);
+    assert_eq!(std::fs::read(&amber_yaml).unwrap(), original);
+
+    let output = edit(
+        dir.path(),
+        &amber_yaml,
+        SECRET_KEY,
+        &["--format", "yaml"],
+        "BAR: SECRET123\nFOO: |\n  first\n  second\nPORT: 8080\n",
+    );
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let seen = std::fs::read_to_string(dir.path().join("seen")).unwrap();
+    assert!(
+        seen.ends_with("\nBAR: SECRET123\nBAZ: something\nFOO: secret1\n"),
+        "{}",
+        seen
+    );
+    assert_eq!(
+        textconv(&amber_yaml, &[]),
+        "# recipients: default\nBAR=SECRET123\nFOO=first\\nsecond\\n\nPORT=8080\n"
+    );
+}
+
+#[test]
+fn edit_environment_overrides() {
+    let dir = tempfile::tempdir().unwrap();
+    let amber_yaml = dir.path().join("amber.yaml");
+    let run = |secret_key: &str, args: &[&str]| {
+        success(
+            amber_with_key(&amber_yaml, secret_key)
+                .args(args)
+                .output()
+                .unwrap(),
+        )
+    };
+    let default_key = run("", &["init", "--only-secret-key"]);
+    run(&default_key, &["encrypt", "LOG_LEVEL", "info"]);
+    run(&default_key, &["encrypt", "DB_URL", "dev-db"]);
+    let prod_key = run(&default_key, &["env", "add", "prod", "--only-secret-key"]);
+    run(
+        &prod_key,
+        &["--env", "prod", "encrypt", "DB_URL", "prod-db"],
+    );
+    let original = std::fs::read(&amber_yaml).unwrap();
+
+    // Removing the line of a default doesn't remove it
+    let output = edit(
+        dir.path(),
+        &amber_yaml,
+        &prod_key,
+        &["--env", "prod"],
+        "DB_URL=prod-db\n",
+    );
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("Defaults can't be removed from an environment")
+            && stderr.contains("LOG_LEVEL"),
+        "{}",
+        stderr
+    );
+    assert_eq!(std::fs::read(&amber_yaml).unwrap(), original);
+
+    // Changing a default overrides it, while removing an override removes it
+    let output = edit(
+        dir.path(),
+        &amber_yaml,
+        &prod_key,
+        &["--env", "prod"],
+        "LOG_LEVEL=debug\n",
+    );
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(
+        run(&prod_key, &["--env", "prod", "print"]),
+        "export DB_URL='dev-db'\nexport LOG_LEVEL='debug'\n"
+    );
+    assert_eq!(
+        run(&default_key, &["print"]),
+        "export DB_URL='dev-db'\nexport LOG_LEVEL='info'\n"
+    );
+}
@@ -1,51 +1,22 @@
-use assert_cmd::prelude::*;
+mod common;
+
+use common::{get_vars, temp_copy, Pair};
 use std::io::Write;
-use std::process::Command;
-use std::{path::Path, process::Stdio};
+use std::process::Stdio;
 
 const AMBER_YAML: &str = "assets/amber-encrypt.yaml";
 const SECRET_KEY: &str = "2a0fb64171010cd4584e2b658fc0a5effca4cd9ada2b2eea0262356852c60872";
 
-fn temp_amber_yaml() -> tempfile::TempPath {
-    let path = tempfile::NamedTempFile::new().unwrap().into_temp_path();
-    std::fs::copy(AMBER_YAML, &path).unwrap();
-    path
-}
-
-#[derive(serde::Deserialize, PartialEq, Eq, Debug)]
-struct Pair {
-    key: String,
-    value: String,
-}
-
-fn get_vars(path: impl AsRef<Path>) -> Vec<Pair> {
-    let output = Command::cargo_bin("amber")
-        .unwrap()
-        .arg("print")
-        .arg("--style")
-        .arg("json")
-        .env("AMBER_YAML", path.as_ref())
-        .env("AMBER_SECRET", SECRET_KEY)
-        .output()
-        .unwrap();
-    if !output.status.success() {
-        eprintln!("{}", std::str::from_utf8(&output.stderr).unwrap());
-        panic!("Did not print successfully");
-    }
-    serde_json::from_slice(&output.stdout).unwrap()
-}
-
 #[test]
 fn empty_file() {
-    let temp = temp_amber_yaml();
-    assert_eq!(get_vars(&temp), vec![]);
+    let temp = temp_copy(AMBER_YAML);
+    assert_eq!(get_vars(&temp, SECRET_KEY), vec![]);
 }
 
 #[test]
 fn encrypt_cli() {
-    let temp = temp_amber_yaml();
-    let status = Command::cargo_bin("amber")
-        .unwrap()
+    let temp = temp_copy(AMBER_YAML);
+    let status = common::amber()
         .arg("encrypt")
         .arg("FOO")
         .arg("foovalue")
@@ -54,7 +25,7 @@ fn encrypt_cli() {
         .unwrap();
     assert!(status.success());
     assert_eq!(
-        get_vars(&temp),
+        get_vars(&temp, SECRET_KEY),
         vec![Pair {
             key: "FOO".to_owned(),
             value: "foovalue".to_owned(),
@@ -64,9 +35,8 @@ fn encrypt_cli() {
 
 #[test]
 fn encrypt_stdin() {
-    let temp = temp_amber_yaml();
-    let mut child = Command::cargo_bin("amber")
-        .unwrap()
+    let temp = temp_copy(AMBER_YAML);
+    let mut child = common::amber()
         .arg("encrypt")
         .arg("FOO")
         .env("AMBER_YAML", temp.as_os_str())
@@ -79,10 +49,84 @@ fn encrypt_stdin() {
     let status = child.wait().unwrap();
     assert!(status.success());
     assert_eq!(
-        get_vars(&temp),
+        get_vars(&temp, SECRET_KEY),
         vec![Pair {
             key: "FOO".to_owned(),
             value: "foovalue via stdin".to_owned(),
         }]
     );
 }
+
+#[test]
+fn rotate_key() {
+    let temp = temp_copy(AMBER_YAML);
+    let status = common::amber()
+        .arg("encrypt")
+        .arg("FOO")
+        .arg("foovalue")
+        .env("AMBER_YAML", temp.as_os_str())
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    let output = common::amber()
+        .arg("rotate-key")
+        .arg("--only-secret-key")
+        .env("AMBER_YAML", temp.as_os_str())
+        .env("AMBER_SECRET", SECRET_KEY)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let new_secret_key = String::from_utf8(output.stdout).unwrap();
+    assert_ne!(new_secret_key, SECRET_KEY);
+
+    assert_eq!(
+        get_vars(&temp, &new_secret_key),
+        vec![Pair {
+            key: "FOO".to_owned(),
+            value: "foovalue".to_owned(),
+        }]
+    );
+
+    let status = common::amber()
+        .arg("print")
+        .env("AMBER_YAML", temp.as_os_str())
+        .env("AMBER_SECRET", SECRET_KEY)
+        .status()
+        .unwrap();
+    assert!(!status.success());
+}
+
+#[cfg(unix)]
+#[test]
+fn saving_keeps_permissions() {
+    use std::os::unix::fs::PermissionsExt;
+    let mode =
+        |path: &std::path::Path| std::fs::metadata(path).unwrap().permissions().mode() & 0o777;
+
+    let temp = temp_copy(AMBER_YAML);
+    std::fs::set_permissions(&temp, std::fs::Permissions::from_mode(0o640)).unwrap();
+    let status = common::amber()
+        .arg("encrypt")
+        .arg("FOO")
+        .arg("foovalue")
+        .env("AMBER_YAML", temp.as_os_str())
+        .status()
+        .unwrap();
+    assert!(status.success());
+    assert_eq!(mode(&temp), 0o640);
+
+    // New files get the permissions allowed by the umask
+    let dir = tempfile::tempdir().unwrap();
+    let amber_yaml = dir.path().join("amber.yaml");
+    let status = common::without_amber_vars(std::process::Command::new("sh"))
+        .arg("-c")
+        .arg("umask 022 && exec \"$0\" init --only-secret-key")
+        .arg(assert_cmd::cargo::cargo_bin("amber"))
+        .env("AMBER_YAML", &amber_yaml)
+        .stdout(Stdio::null())
+        .status()
+        .unwrap();
+    assert!(status.success());
+    assert_eq!(mode(&amber_yaml), 0o644);
+}
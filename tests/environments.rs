@@ -0,0 +1,132 @@
+mod common;
+
+use common::{amber_file, success};
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::process::Output;
+
+fn print(amber_yaml: &Path, env: Option<&str>, secret_key: &str) -> Output {
+    let mut cmd = amber_file(amber_yaml);
+    if let Some(env) = env {
+        cmd.arg("--env").arg(env);
+    }
+    cmd.args(["print", "--style", "json"])
+        .env("AMBER_SECRET", secret_key)
+        .output()
+        .unwrap()
+}
+
+fn secrets(output: Output) -> BTreeMap<String, String> {
+    let pairs: Vec<serde_json::Value> = serde_json::from_str(&success(output)).unwrap();
+    pairs
+        .into_iter()
+        .map(|pair| {
+            (
+                pair["key"].as_str().unwrap().to_owned(),
+                pair["value"].as_str().unwrap().to_owned(),
+            )
+        })
+        .collect()
+}
+
+#[test]
+fn environments_override_defaults() {
+    let dir = tempfile::tempdir().unwrap();
+    let amber_yaml = dir.path().join("amber.yaml");
+    let default_key = success(
+        amber_file(&amber_yaml)
+            .args(["init", "--only-secret-key"])
+            .output()
+            .unwrap(),
+    );
+    for (key, value) in [("LOG_LEVEL", "info"), ("DB_URL", "dev-db")] {
+        success(
+            amber_file(&amber_yaml)
+                .args(["encrypt", key, value])
+                .output()
+                .unwrap(),
+        );
+    }
+
+    let prod_key = success(
+        amber_file(&amber_yaml)
+            .args(["env", "add", "prod", "--only-secret-key"])
+            .env("AMBER_SECRET", &default_key)
+            .output()
+            .unwrap(),
+    );
+    assert_eq!(
+        success(
+            amber_file(&amber_yaml)
+                .args(["env", "list"])
+                .output()
+                .unwrap()
+        ),
+        "prod prod\n"
+    );
+    success(
+        amber_file(&amber_yaml)
+            .args(["--env", "prod", "encrypt", "DB_URL", "prod-db"])
+            .output()
+            .unwrap(),
+    );
+
+    let prod = secrets(print(&amber_yaml, Some("prod"), &prod_key));
+    assert_eq!(prod["DB_URL"], "prod-db");
+    assert_eq!(prod["LOG_LEVEL"], "info");
+    let defaults = secrets(print(&amber_yaml, None, &default_key));
+    assert_eq!(defaults["DB_URL"], "dev-db");
+    assert_eq!(secrets(print(&amber_yaml, None, &prod_key)), defaults);
+    assert!(!print(&amber_yaml, Some("prod"), &default_key)
+        .status
+        .success());
+    assert!(!print(&amber_yaml, Some("staging"), &default_key)
+        .status
+        .success());
+
+    // New recipients of an environment can read its secrets
+    let ci_key = success(
+        amber_file(&amber_yaml)
+            .args([
+                "--env",
+                "prod",
+                "recipient",
+                "add",
+                "ci",
+                "--only-secret-key",
+            ])
+            .env("AMBER_SECRET", &prod_key)
+            .output()
+            .unwrap(),
+    );
+    assert_eq!(secrets(print(&amber_yaml, Some("prod"), &ci_key)), prod);
+
+    // The default ciphertext cannot pass as the environment's
+    let original = std::fs::read_to_string(&amber_yaml).unwrap();
+    let mut yaml: serde_yaml::Value = serde_yaml::from_str(&original).unwrap();
+    let default_cipher = yaml["secrets"]
+        .as_sequence()
+        .unwrap()
+        .iter()
+        .find(|secret| secret["name"] == "DB_URL")
+        .unwrap()["ciphers"]["prod"]
+        .clone();
+    yaml["environments"][0]["secrets"][0]["ciphers"]["prod"] = default_cipher;
+    std::fs::write(&amber_yaml, serde_yaml::to_string(&yaml).unwrap()).unwrap();
+    let output = print(&amber_yaml, Some("prod"), &prod_key);
+    assert!(!output.status.success());
+    assert!(String::from_utf8(output.stderr)
+        .unwrap()
+        .contains("tampered"));
+    std::fs::write(&amber_yaml, original).unwrap();
+
+    success(
+        amber_file(&amber_yaml)
+            .args(["env", "remove", "prod"])
+            .env("AMBER_SECRET", &default_key)
+            .output()
+            .unwrap(),
+    );
+    assert!(!print(&amber_yaml, None, &prod_key).status.success());
+    assert_eq!(secrets(print(&amber_yaml, None, &default_key)), defaults);
+}
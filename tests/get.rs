@@ -0,0 +1,59 @@
+mod common;
+
+use common::{amber_file, amber_with_key, stderr, AMBER_YAML, KEYTAB, SECRET_KEY};
+use std::path::Path;
+
+fn get(amber_yaml: &Path, args: &[&str]) -> Vec<u8> {
+    let output = amber_with_key(amber_yaml, SECRET_KEY)
+        .args(args)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+    output.stdout
+}
+
+#[test]
+fn get_single_values() {
+    let dir = tempfile::tempdir().unwrap();
+    let amber_yaml = dir.path().join("amber.yaml");
+    std::fs::copy(AMBER_YAML, &amber_yaml).unwrap();
+    // Binary values need the latest file format version
+    get(&amber_yaml, &["migrate"]);
+    let keytab = dir.path().join("krb5.keytab");
+    std::fs::write(&keytab, KEYTAB).unwrap();
+    get(
+        &amber_yaml,
+        &[
+            "encrypt",
+            "KEYTAB",
+            "--binary",
+            "--file",
+            keytab.to_str().unwrap(),
+        ],
+    );
+
+    assert_eq!(get(&amber_yaml, &["get", "BAR"]), b"SECRET123\n");
+    assert_eq!(get(&amber_yaml, &["get", "-n", "BAR"]), b"SECRET123");
+    assert_eq!(
+        get(&amber_yaml, &["get", "--json", "BAR"]),
+        br#"{"key":"BAR","value":"SECRET123"}"#
+    );
+    assert_eq!(get(&amber_yaml, &["get", "KEYTAB"]), KEYTAB);
+    assert_eq!(
+        get(&amber_yaml, &["get", "--json", "KEYTAB"]),
+        br#"{"key":"KEYTAB","value":"BQIAAABB//6AYmluYXJ5AA==","encoding":"base64"}"#
+    );
+
+    let output = amber_with_key(&amber_yaml, SECRET_KEY)
+        .args(["get", "MISSING"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(output.stdout.is_empty());
+    assert!(stderr(&output).contains("Key does not exist: MISSING"));
+
+    // Names are listed without a secret key
+    let output = amber_file(&amber_yaml).arg("list").output().unwrap();
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"BAR\nBAZ\nFOO\nKEYTAB\n");
+}
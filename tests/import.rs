@@ -0,0 +1,128 @@
+mod common;
+
+use common::{amber_with_key, success, AMBER_YAML, KEYTAB, SECRET_KEY};
+use std::path::Path;
+
+fn textconv(amber_yaml: &Path) -> String {
+    success(
+        amber_with_key(amber_yaml, SECRET_KEY)
+            .arg("textconv")
+            .arg(amber_yaml)
+            .output()
+            .unwrap(),
+    )
+}
+
+#[test]
+fn import_dotenv() {
+    let dir = tempfile::tempdir().unwrap();
+    let amber_yaml = dir.path().join("amber.yaml");
+    std::fs::copy(AMBER_YAML, &amber_yaml).unwrap();
+    let original = std::fs::read(&amber_yaml).unwrap();
+    let env = dir.path().join(".env");
+    std::fs::write(
+        &env,
+        "# Settings\nexport DB_URL=postgres://db/app # production\n\nGREETING='Hello $USER'\nCERT=\"first\nsecond\\t\\\"quoted\\\"\"\nFOO=new\n",
+    )
+    .unwrap();
+    let import = |args: &[&str]| {
+        amber_with_key(&amber_yaml, SECRET_KEY)
+            .arg("import")
+            .arg(&env)
+            .args(args)
+            .output()
+            .unwrap()
+    };
+
+    let output = import(&[]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("already exist: FOO"), "{}", stderr);
+
+    let output = import(&["--overwrite", "--dry-run"]);
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(output.status.success(), "{}", stderr);
+    assert!(stderr.contains("Overwriting FOO"), "{}", stderr);
+    assert!(stderr.contains("Adding DB_URL"), "{}", stderr);
+    assert_eq!(std::fs::read(&amber_yaml).unwrap(), original);
+
+    success(import(&["--skip-existing"]));
+    assert_eq!(
+        textconv(&amber_yaml),
+        "# recipients: default\nBAR=SECRET123\nBAZ=something\nCERT=first\\nsecond\\t\"quoted\"\nDB_URL=postgres://db/app\nFOO=secret1\nGREETING=Hello $USER\n"
+    );
+    success(import(&["--overwrite"]));
+    assert!(textconv(&amber_yaml).contains("\nFOO=new\n"));
+}
+
+#[test]
+fn import_print_output_and_environment() {
+    let dir = tempfile::tempdir().unwrap();
+    let source = dir.path().join("source.yaml");
+    std::fs::copy(AMBER_YAML, &source).unwrap();
+    // Binary values need the latest file format version
+    success(
+        amber_with_key(&source, SECRET_KEY)
+            .arg("migrate")
+            .output()
+            .unwrap(),
+    );
+    let keytab = dir.path().join("krb5.keytab");
+    std::fs::write(&keytab, KEYTAB).unwrap();
+    success(
+        amber_with_key(&source, SECRET_KEY)
+            .args(["encrypt", "KEYTAB", "--binary", "--file"])
+            .arg(&keytab)
+            .output()
+            .unwrap(),
+    );
+
+    for style in ["json", "yaml"] {
+        let exported = dir.path().join(format!("secrets.{style}"));
+        let printed = success(
+            amber_with_key(&source, SECRET_KEY)
+                .args(["print", "--style", style])
+                .output()
+                .unwrap(),
+        );
+        std::fs::write(&exported, printed).unwrap();
+        let dest = dir.path().join(format!("{style}.yaml"));
+        std::fs::copy(AMBER_YAML, &dest).unwrap();
+        success(
+            amber_with_key(&dest, SECRET_KEY)
+                .arg("migrate")
+                .output()
+                .unwrap(),
+        );
+        success(
+            amber_with_key(&dest, SECRET_KEY)
+                .args(["remove", "FOO"])
+                .output()
+                .unwrap(),
+        );
+        success(
+            amber_with_key(&dest, SECRET_KEY)
+                .args(["import", "--skip-existing"])
+                .arg(&exported)
+                .output()
+                .unwrap(),
+        );
+        assert_eq!(textconv(&dest), textconv(&source));
+    }
+
+    let dest = dir.path().join("env.yaml");
+    std::fs::copy(AMBER_YAML, &dest).unwrap();
+    success(
+        amber_with_key(&dest, SECRET_KEY)
+            .args(["import", "--from-env", "MYAPP_"])
+            .env("MYAPP_TOKEN", "token with spaces")
+            .env("MYAPP_PORT", "8080")
+            .env("OTHER_TOKEN", "ignored")
+            .output()
+            .unwrap(),
+    );
+    assert_eq!(
+        textconv(&dest),
+        "# recipients: default\nBAR=SECRET123\nBAZ=something\nFOO=secret1\nMYAPP_PORT=8080\nMYAPP_TOKEN=token with spaces\n"
+    );
+}
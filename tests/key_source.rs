@@ -0,0 +1,144 @@
+mod common;
+
+use common::{stderr, write_key, AMBER_YAML, SECRET_KEY};
+use std::process::{Command, Output};
+
+fn print(configure: impl FnOnce(&mut Command)) -> Output {
+    let mut cmd = common::amber();
+    cmd.arg("print")
+        .env("AMBER_YAML", AMBER_YAML)
+        .env_remove("CREDENTIALS_DIRECTORY");
+    configure(&mut cmd);
+    cmd.output().unwrap()
+}
+
+#[test]
+fn secret_key_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let key = dir.path().join("key");
+    write_key(&key, &format!("{SECRET_KEY}\n"), 0o600);
+    let output = print(|cmd| {
+        cmd.arg("--secret-key-file").arg(&key);
+    });
+    assert!(output.status.success(), "{}", stderr(&output));
+
+    let output = print(|cmd| {
+        cmd.env("AMBER_SECRET_FILE", &key);
+    });
+    assert!(output.status.success(), "{}", stderr(&output));
+}
+
+#[cfg(unix)]
+#[test]
+fn world_readable_key_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let key = dir.path().join("key");
+    write_key(&key, SECRET_KEY, 0o644);
+    let output = print(|cmd| {
+        cmd.arg("--secret-key-file").arg(&key);
+    });
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("accessible by other users"));
+}
+
+#[test]
+fn missing_and_malformed_keys() {
+    let output = print(|_| {});
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("No secret key provided"));
+
+    let dir = tempfile::tempdir().unwrap();
+    let key = dir.path().join("key");
+    let output = print(|cmd| {
+        cmd.arg("--secret-key-file").arg(&key);
+    });
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("does not exist"));
+
+    write_key(&key, "not a key", 0o600);
+    let output = print(|cmd| {
+        cmd.arg("--secret-key-file").arg(&key);
+    });
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("Malformed secret key in file"));
+}
+
+#[test]
+fn systemd_credentials() {
+    let dir = tempfile::tempdir().unwrap();
+    write_key(&dir.path().join("amber-secret"), SECRET_KEY, 0o400);
+    let output = print(|cmd| {
+        cmd.env("CREDENTIALS_DIRECTORY", dir.path());
+    });
+    assert!(output.status.success(), "{}", stderr(&output));
+}
+
+#[cfg(unix)]
+#[test]
+fn key_plugin() {
+    const PUBLIC_KEY: &str = "f25ff634eb2e6d46b18ab5e846e5c051fafd15af1520b4f2cdc2bc09297bb442";
+    let path = std::env::join_paths(
+        std::iter::once(std::fs::canonicalize("assets").unwrap())
+            .chain(std::env::split_paths(&std::env::var_os("PATH").unwrap())),
+    )
+    .unwrap();
+
+    let output = print(|cmd| {
+        cmd.arg("--key-plugin")
+            .arg("test")
+            .env("PATH", &path)
+            .env("AMBER_TEST_PLUGIN_PUBLIC", PUBLIC_KEY)
+            .env("AMBER_TEST_PLUGIN_SECRET", SECRET_KEY);
+    });
+    assert!(output.status.success(), "{}", stderr(&output));
+
+    let output = print(|cmd| {
+        cmd.env("AMBER_KEY_PLUGIN", "test")
+            .env("PATH", &path)
+            .env("AMBER_TEST_PLUGIN_PUBLIC", "somebody-else")
+            .env("AMBER_TEST_PLUGIN_SECRET", SECRET_KEY);
+    });
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("no identity for this file"));
+
+    let output = print(|cmd| {
+        cmd.arg("--key-plugin").arg("does-not-exist");
+    });
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("amber-key-does-not-exist"));
+}
+
+#[test]
+fn passphrase_protected_key_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let amber_yaml = dir.path().join("amber.yaml");
+    let key = dir.path().join("key");
+    let status = common::amber()
+        .arg("init")
+        .arg("--passphrase")
+        .arg("--secret-key-file")
+        .arg(&key)
+        .env("AMBER_YAML", &amber_yaml)
+        .env("AMBER_PASSPHRASE", "correct horse")
+        .status()
+        .unwrap();
+    assert!(status.success());
+    assert!(std::fs::read_to_string(&key)
+        .unwrap()
+        .contains("amber-passphrase-secret-key"));
+
+    let run = |passphrase: &str| {
+        common::amber()
+            .arg("print")
+            .env("AMBER_YAML", &amber_yaml)
+            .env("AMBER_SECRET_FILE", &key)
+            .env("AMBER_PASSPHRASE", passphrase)
+            .output()
+            .unwrap()
+    };
+    let output = run("correct horse");
+    assert!(output.status.success(), "{}", stderr(&output));
+    let output = run("battery staple");
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("Wrong passphrase"));
+}
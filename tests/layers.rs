@@ -0,0 +1,152 @@
+mod common;
+
+use common::{amber_file, success};
+use std::path::Path;
+
+fn encrypt(amber_yaml: &Path, key: &str, value: &str) {
+    success(
+        amber_file(amber_yaml)
+            .args(["encrypt", key, value])
+            .output()
+            .unwrap(),
+    );
+}
+
+#[test]
+fn service_file_extends_root_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path().join("amber.yaml");
+    let service_dir = dir.path().join("services").join("api");
+    std::fs::create_dir_all(&service_dir).unwrap();
+    let service = service_dir.join("amber.yaml");
+
+    let root_key = success(
+        amber_file(&root)
+            .args(["init", "--only-secret-key"])
+            .output()
+            .unwrap(),
+    );
+    encrypt(&root, "SHARED", "from-root");
+    encrypt(&root, "DB_URL", "root-db");
+
+    let output = amber_file(&service)
+        .args(["init", "--extends", "../missing.yaml"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let service_key = success(
+        amber_file(&service)
+            .args(["init", "--only-secret-key", "--extends", "../../amber.yaml"])
+            .output()
+            .unwrap(),
+    );
+    encrypt(&service, "DB_URL", "api-db");
+
+    // The service key can't decrypt the root file
+    let output = amber_file(&service)
+        .arg("print")
+        .env("AMBER_SECRET", &service_key)
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8(output.stderr)
+        .unwrap()
+        .contains("Unable to decrypt"));
+
+    // Once the root key can decrypt the service file, it sees both
+    let root_public_key = success(
+        amber_file(&root)
+            .args(["recipient", "list"])
+            .output()
+            .unwrap(),
+    );
+    let root_public_key = root_public_key.split_whitespace().nth(1).unwrap();
+    success(
+        amber_file(&service)
+            .args(["recipient", "add", "root", root_public_key])
+            .env("AMBER_SECRET", &service_key)
+            .output()
+            .unwrap(),
+    );
+
+    let output = success(
+        amber_file(&service)
+            .args(["print", "--style", "json", "--show-source"])
+            .env("AMBER_SECRET", &root_key)
+            .output()
+            .unwrap(),
+    );
+    let secrets: serde_json::Value = serde_json::from_str(&output).unwrap();
+    assert_eq!(secrets[0]["key"], "DB_URL");
+    assert_eq!(secrets[0]["value"], "api-db");
+    assert_eq!(secrets[0]["source"], service.display().to_string());
+    assert_eq!(secrets[1]["key"], "SHARED");
+    assert_eq!(secrets[1]["value"], "from-root");
+    assert!(secrets[1]["source"]
+        .as_str()
+        .unwrap()
+        .ends_with("../../amber.yaml"));
+
+    let output = success(
+        amber_file(&service)
+            .args([
+                "--unmasked",
+                "exec",
+                "sh",
+                "--",
+                "-c",
+                "echo $SHARED $DB_URL",
+            ])
+            .env("AMBER_SECRET", &root_key)
+            .output()
+            .unwrap(),
+    );
+    assert_eq!(output, "from-root api-db\n");
+
+    let dest = dir.path().join("shared.txt");
+    success(
+        amber_file(&service)
+            .args(["write-file", "--key", "SHARED", "--dest"])
+            .arg(&dest)
+            .env("AMBER_SECRET", &root_key)
+            .output()
+            .unwrap(),
+    );
+    assert_eq!(std::fs::read_to_string(&dest).unwrap(), "from-root");
+
+    // Cycles are detected
+    let contents = std::fs::read_to_string(&root).unwrap();
+    std::fs::write(
+        &root,
+        contents.replace(
+            "file_format_version: 5\n",
+            "file_format_version: 5\nextends: services/api/amber.yaml\n",
+        ),
+    )
+    .unwrap();
+    let output = amber_file(&service)
+        .arg("print")
+        .env("AMBER_SECRET", &root_key)
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8(output.stderr).unwrap().contains("cycle"));
+}
+
+#[test]
+fn only_version_5_files_extend() {
+    let dir = tempfile::tempdir().unwrap();
+    let amber_yaml = dir.path().join("amber.yaml");
+    let mut contents = std::fs::read_to_string("assets/amber-v3.yaml").unwrap();
+    contents = contents.replacen("file_id:", "extends: root.yaml\nfile_id:", 1);
+    std::fs::write(&amber_yaml, contents).unwrap();
+
+    let output = amber_file(&amber_yaml).arg("list").output().unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.contains("Only version 5 and later files extend other files"),
+        "{}",
+        stderr
+    );
+}
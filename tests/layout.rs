@@ -0,0 +1,146 @@
+mod common;
+
+use common::{amber_in, success};
+use std::path::Path;
+
+fn print(dir: &Path, secret_key: &str) -> String {
+    success(
+        amber_in(dir)
+            .args(["print", "--style", "json"])
+            .env("AMBER_SECRET", secret_key)
+            .output()
+            .unwrap(),
+    )
+}
+
+#[test]
+fn directory_layout() {
+    let dir = tempfile::tempdir().unwrap();
+    let dir = dir.path();
+    let amber_dir = dir.join(".amber");
+    let secret_key = success(
+        amber_in(dir)
+            .args(["init", "--layout", "directory", "--only-secret-key"])
+            .output()
+            .unwrap(),
+    );
+    for (key, value) in [("API_KEY", "api"), ("DB_URL", "db"), ("OLD", "old")] {
+        success(
+            amber_in(dir)
+                .args(["encrypt", key, value])
+                .output()
+                .unwrap(),
+        );
+    }
+    let manifest = std::fs::read_to_string(amber_dir.join("amber.yaml")).unwrap();
+    assert!(!manifest.contains("secrets"));
+    assert!(amber_dir.join("secrets/DB_URL.yaml").is_file());
+
+    // Commands find the directory from subdirectories, like amber.yaml
+    let subdir = dir.join("src");
+    std::fs::create_dir(&subdir).unwrap();
+    success(amber_in(&subdir).args(["remove", "OLD"]).output().unwrap());
+    assert!(!amber_dir.join("secrets/OLD.yaml").exists());
+    let secrets = print(&subdir, &secret_key);
+    assert!(secrets.contains("\"API_KEY\""));
+    assert!(!secrets.contains("\"OLD\""));
+
+    // Unchanged secrets keep their files untouched
+    let db_url = amber_dir.join("secrets/DB_URL.yaml");
+    let modified = std::fs::metadata(&db_url).unwrap().modified().unwrap();
+    success(
+        amber_in(dir)
+            .args(["encrypt", "API_KEY", "new"])
+            .output()
+            .unwrap(),
+    );
+    let modified_after = std::fs::metadata(&db_url).unwrap().modified().unwrap();
+    assert_eq!(modified_after, modified);
+
+    // Names must be usable as file names
+    let output = amber_in(dir)
+        .args(["encrypt", "../ESCAPE", "value"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(!dir.join("ESCAPE.yaml").exists());
+
+    // Renamed files are detected
+    std::fs::rename(&db_url, amber_dir.join("secrets/OTHER.yaml")).unwrap();
+    assert!(!amber_in(dir).arg("list").output().unwrap().status.success());
+    std::fs::rename(amber_dir.join("secrets/OTHER.yaml"), &db_url).unwrap();
+
+    // Converting back and forth keeps the secrets
+    let before = print(dir, &secret_key);
+    let output = amber_in(dir)
+        .args(["convert", "directory"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    success(amber_in(dir).args(["convert", "file"]).output().unwrap());
+    assert!(!amber_dir.exists());
+    assert!(dir.join("amber.yaml").is_file());
+    assert_eq!(print(dir, &secret_key), before);
+    success(
+        amber_in(dir)
+            .args(["convert", "directory"])
+            .output()
+            .unwrap(),
+    );
+    assert!(!dir.join("amber.yaml").exists());
+    assert_eq!(print(dir, &secret_key), before);
+
+    // Both layouts in one directory are ambiguous
+    std::fs::write(dir.join("amber.yaml"), "").unwrap();
+    assert!(!amber_in(dir).arg("list").output().unwrap().status.success());
+}
+
+#[test]
+fn directory_layout_environments() {
+    let dir = tempfile::tempdir().unwrap();
+    let dir = dir.path();
+    let amber_dir = dir.join(".amber");
+    let secret_key = success(
+        amber_in(dir)
+            .args(["init", "--layout", "directory", "--only-secret-key"])
+            .output()
+            .unwrap(),
+    );
+    success(
+        amber_in(dir)
+            .args(["encrypt", "DB_URL", "dev"])
+            .output()
+            .unwrap(),
+    );
+    let prod_key = success(
+        amber_in(dir)
+            .args(["env", "add", "prod", "--only-secret-key"])
+            .env("AMBER_SECRET", &secret_key)
+            .output()
+            .unwrap(),
+    );
+    success(
+        amber_in(dir)
+            .args(["--env", "prod", "encrypt", "DB_URL", "prod"])
+            .output()
+            .unwrap(),
+    );
+    assert!(amber_dir.join("environments/prod/DB_URL.yaml").is_file());
+    let output = success(
+        amber_in(dir)
+            .args(["--env", "prod", "print"])
+            .env("AMBER_SECRET", &prod_key)
+            .output()
+            .unwrap(),
+    );
+    assert_eq!(output, "export DB_URL='prod'\n");
+
+    success(
+        amber_in(dir)
+            .args(["env", "remove", "prod"])
+            .env("AMBER_SECRET", &secret_key)
+            .output()
+            .unwrap(),
+    );
+    assert!(!amber_dir.join("environments/prod").exists());
+}
@@ -0,0 +1,75 @@
+mod common;
+
+use common::{amber_in, git, success};
+
+/// The changes listed by amber log, without commit IDs
+fn changes(log: &str) -> Vec<&str> {
+    log.lines()
+        .map(|line| match line.strip_prefix("    ") {
+            Some(value) => value,
+            None => line.split_once(' ').unwrap().1,
+        })
+        .collect()
+}
+
+#[test]
+fn log_secret_history() {
+    let dir = tempfile::tempdir().unwrap();
+    let dir = dir.path();
+    git(dir, &["init", "--quiet"]);
+    let secret_key = success(
+        amber_in(dir)
+            .args(["init", "--only-secret-key"])
+            .output()
+            .unwrap(),
+    );
+    let commit = |args: &[&str], message: &str| {
+        success(
+            amber_in(dir)
+                .args(args)
+                .env("AMBER_SECRET", &secret_key)
+                .output()
+                .unwrap(),
+        );
+        git(dir, &["add", "-A"]);
+        git(dir, &["commit", "--quiet", "-m", message]);
+    };
+    commit(&["encrypt", "STRIPE_KEY", "first"], "Add Stripe key");
+    commit(&["encrypt", "OTHER", "other"], "Add another secret");
+    commit(&["encrypt", "STRIPE_KEY", "second"], "Rotate Stripe key");
+    commit(&["convert", "directory"], "Use the directory layout");
+    commit(&["remove", "STRIPE_KEY"], "Remove Stripe key");
+
+    let log = success(amber_in(dir).args(["log", "STRIPE_KEY"]).output().unwrap());
+    assert_eq!(
+        changes(&log),
+        [
+            "2025-06-30 removed by Jane Doe <jane@example.com>: Remove Stripe key",
+            "2025-06-30 changed by Jane Doe <jane@example.com>: Rotate Stripe key",
+            "2025-06-30 added by Jane Doe <jane@example.com>: Add Stripe key",
+        ]
+    );
+
+    let log = success(
+        amber_in(dir)
+            .args(["--rev", "HEAD~1", "log", "STRIPE_KEY", "--show-values"])
+            .env("AMBER_SECRET", &secret_key)
+            .output()
+            .unwrap(),
+    );
+    assert_eq!(
+        changes(&log),
+        [
+            "2025-06-30 changed by Jane Doe <jane@example.com>: Rotate Stripe key",
+            "value: second",
+            "2025-06-30 added by Jane Doe <jane@example.com>: Add Stripe key",
+            "value: first",
+        ]
+    );
+
+    let output = amber_in(dir)
+        .args(["log", "STRIPE_KEY", "--show-values"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+}
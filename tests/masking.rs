@@ -1,15 +1,13 @@
-use assert_cmd::prelude::*;
-use std::process::Command;
+mod common;
+
+use common::{AMBER_YAML, SECRET_KEY};
 
-const AMBER_YAML: &str = "assets/amber-masking.yaml";
-const SECRET_KEY: &str = "ac2af4852f3de2dc6feb19b718d1cbf6c64c1ef618dafaf2b0a89cadcde240ac";
 const TO_MASK: &str = include_str!("../assets/tomask.txt");
 const MASKED: &str = include_str!("../assets/masked.txt");
 
 #[test]
 fn masking() {
-    let output = Command::cargo_bin("amber")
-        .unwrap()
+    let output = common::amber()
         .arg("exec")
         .arg("cat")
         .arg("assets/tomask.txt")
@@ -26,8 +24,7 @@ fn masking() {
 
 #[test]
 fn disable_masking() {
-    let output = Command::cargo_bin("amber")
-        .unwrap()
+    let output = common::amber()
         .arg("exec")
         .arg("--unmasked")
         .arg("cat")
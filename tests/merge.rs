@@ -0,0 +1,169 @@
+mod common;
+
+use common::{amber_with_key, success, AMBER_YAML, SECRET_KEY};
+use std::path::Path;
+use std::process::Output;
+
+fn run(amber_yaml: &Path, args: &[&str]) {
+    success(
+        amber_with_key(amber_yaml, SECRET_KEY)
+            .args(args)
+            .output()
+            .unwrap(),
+    );
+}
+
+fn merge(base: &Path, ours: &Path, theirs: &Path) -> Output {
+    common::amber()
+        .arg("merge-driver")
+        .args([base, ours, theirs])
+        .output()
+        .unwrap()
+}
+
+fn print(amber_yaml: &Path) -> String {
+    success(
+        amber_with_key(amber_yaml, SECRET_KEY)
+            .arg("print")
+            .output()
+            .unwrap(),
+    )
+}
+
+#[test]
+fn merge_secrets_changed_on_different_sides() {
+    let dir = tempfile::tempdir().unwrap();
+    let base = dir.path().join("base.yaml");
+    let ours = dir.path().join("ours.yaml");
+    let theirs = dir.path().join("theirs.yaml");
+    std::fs::copy(AMBER_YAML, &base).unwrap();
+    run(&base, &["migrate"]);
+    std::fs::copy(&base, &ours).unwrap();
+    std::fs::copy(&base, &theirs).unwrap();
+
+    run(&ours, &["encrypt", "FOO", "ours"]);
+    run(&ours, &["encrypt", "OURS", "added"]);
+    run(&theirs, &["encrypt", "BAR", "theirs"]);
+    run(&theirs, &["remove", "BAZ"]);
+    run(&theirs, &["encrypt", "THEIRS", "added"]);
+
+    success(merge(&base, &ours, &theirs));
+    assert_eq!(
+        print(&ours),
+        "export BAR='theirs'\nexport FOO='ours'\nexport OURS='added'\nexport THEIRS='added'\n"
+    );
+}
+
+#[test]
+fn merge_conflicts() {
+    let dir = tempfile::tempdir().unwrap();
+    let base = dir.path().join("base.yaml");
+    let ours = dir.path().join("ours.yaml");
+    let theirs = dir.path().join("theirs.yaml");
+    std::fs::copy(AMBER_YAML, &base).unwrap();
+    run(&base, &["migrate"]);
+    std::fs::copy(&base, &ours).unwrap();
+    std::fs::copy(&base, &theirs).unwrap();
+
+    // The same change on both sides is no conflict
+    run(&ours, &["encrypt", "FOO", "same"]);
+    run(&theirs, &["encrypt", "FOO", "same"]);
+    run(&ours, &["encrypt", "BAR", "ours"]);
+    run(&theirs, &["encrypt", "BAR", "theirs"]);
+
+    let output = merge(&base, &ours, &theirs);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.contains("secret BAR changed on both sides"),
+        "{}",
+        stderr
+    );
+    assert!(!stderr.contains("FOO"), "{}", stderr);
+    // Conflicting secrets keep our version
+    assert!(print(&ours).contains("export BAR='ours'"));
+
+    // Adding recipients re-encrypts everything, so any other change conflicts
+    let before = std::fs::read(&ours).unwrap();
+    run(&theirs, &["recipient", "add", "ci", "--only-secret-key"]);
+    let output = merge(&base, &ours, &theirs);
+    assert!(!output.status.success());
+    assert_eq!(std::fs::read(&ours).unwrap(), before);
+}
+
+#[test]
+fn merge_secrets_added_before_a_key_rotation() {
+    let dir = tempfile::tempdir().unwrap();
+    let base = dir.path().join("base.yaml");
+    let ours = dir.path().join("ours.yaml");
+    let theirs = dir.path().join("theirs.yaml");
+    std::fs::copy(AMBER_YAML, &base).unwrap();
+    run(&base, &["migrate"]);
+    std::fs::copy(&base, &ours).unwrap();
+    std::fs::copy(&base, &theirs).unwrap();
+
+    let new_key = success(
+        amber_with_key(&ours, SECRET_KEY)
+            .args(["rotate-key", "--only-secret-key"])
+            .output()
+            .unwrap(),
+    );
+    run(&theirs, &["encrypt", "NEW", "new"]);
+
+    // Their secret is sealed for the revoked key, which the merged file no longer has
+    let output = merge(&base, &ours, &theirs);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr
+            .contains("secret NEW is encrypted for recipients other than those of the merged file"),
+        "{}",
+        stderr
+    );
+    assert_eq!(
+        success(
+            amber_with_key(&ours, &new_key)
+                .arg("print")
+                .output()
+                .unwrap()
+        ),
+        "export BAR='SECRET123'\nexport BAZ='something'\nexport FOO='secret1'\n"
+    );
+}
+
+#[test]
+fn merge_same_values_before_version_4() {
+    let dir = tempfile::tempdir().unwrap();
+    let base = dir.path().join("base.yaml");
+    let ours = dir.path().join("ours.yaml");
+    let theirs = dir.path().join("theirs.yaml");
+    // Version 1 and 3 files compare values by their plain SHA-256
+    for (asset, secret_key) in [
+        (AMBER_YAML, SECRET_KEY),
+        (
+            "assets/amber-v3.yaml",
+            "beff070c4e39ae1eb56c45cdda904b439294e06432c6d07012529bc1257503a5",
+        ),
+    ] {
+        std::fs::copy(asset, &base).unwrap();
+        std::fs::copy(&base, &ours).unwrap();
+        std::fs::copy(&base, &theirs).unwrap();
+        for path in [&ours, &theirs] {
+            success(
+                amber_with_key(path, secret_key)
+                    .args(["encrypt", "FOO", "same"])
+                    .output()
+                    .unwrap(),
+            );
+        }
+
+        success(merge(&base, &ours, &theirs));
+        let printed = success(
+            amber_with_key(&ours, secret_key)
+                .arg("print")
+                .output()
+                .unwrap(),
+        );
+        assert!(printed.contains("export FOO='same'"), "{}", printed);
+    }
+}
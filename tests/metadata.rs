@@ -0,0 +1,114 @@
+mod common;
+
+use common::{amber_file, temp_copy, AMBER_YAML, SECRET_KEY};
+use std::path::Path;
+
+fn list_json(amber_yaml: &Path) -> serde_json::Value {
+    let output = amber_file(amber_yaml)
+        .arg("list")
+        .arg("--json")
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    serde_json::from_slice(&output.stdout).unwrap()
+}
+
+#[test]
+fn metadata_is_set_listed_and_preserved() {
+    let temp = temp_copy(AMBER_YAML);
+
+    // Older file format versions can't hold metadata, not even timestamps
+    let output = amber_file(&temp)
+        .args([
+            "encrypt",
+            "LEGACY_TOKEN",
+            "token",
+            "--owner",
+            "billing-team",
+        ])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let stderr = std::str::from_utf8(&output.stderr).unwrap();
+    assert!(stderr.contains("Run amber migrate"), "{}", stderr);
+    let status = amber_file(&temp)
+        .args(["encrypt", "LEGACY_TOKEN", "token"])
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let contents = std::fs::read_to_string(&temp).unwrap();
+    assert!(!contents.contains("created_at"), "{}", contents);
+    let status = amber_file(&temp)
+        .args(["remove", "LEGACY_TOKEN"])
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    let status = amber_file(&temp)
+        .arg("migrate")
+        .env("AMBER_SECRET", SECRET_KEY)
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let status = amber_file(&temp)
+        .args(["encrypt", "LEGACY_TOKEN", "token"])
+        .args(["--description", "Token for the legacy billing API"])
+        .args([
+            "--owner",
+            "billing-team",
+            "--tag",
+            "billing",
+            "--tag",
+            "prod",
+        ])
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    let output = amber_file(&temp).arg("list").output().unwrap();
+    assert!(output.status.success());
+    assert_eq!(
+        std::str::from_utf8(&output.stdout).unwrap(),
+        "BAR\nBAZ\nFOO\nLEGACY_TOKEN\n"
+    );
+
+    let list = list_json(&temp);
+    let token = &list[3];
+    assert_eq!(token["name"], "LEGACY_TOKEN");
+    assert_eq!(token["description"], "Token for the legacy billing API");
+    assert_eq!(token["owner"], "billing-team");
+    assert_eq!(token["tags"], serde_json::json!(["billing", "prod"]));
+    let created_at = token["created_at"].clone();
+    assert!(created_at.is_string());
+    assert!(list[0].get("created_at").is_none());
+
+    // Updating the value keeps the metadata unless it's overridden
+    let status = amber_file(&temp)
+        .args(["encrypt", "LEGACY_TOKEN", "new-token", "--tag", ""])
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let token = &list_json(&temp)[3];
+    assert_eq!(token["owner"], "billing-team");
+    assert!(token.get("tags").is_none());
+    assert_eq!(token["created_at"], created_at);
+
+    // Re-encrypting every secret keeps the metadata too
+    let status = amber_file(&temp)
+        .arg("migrate")
+        .env("AMBER_SECRET", SECRET_KEY)
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let token = &list_json(&temp)[3];
+    assert_eq!(token["description"], "Token for the legacy billing API");
+
+    let output = amber_file(&temp)
+        .arg("list")
+        .arg("--long")
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stdout = std::str::from_utf8(&output.stdout).unwrap();
+    assert!(stdout.contains("LEGACY_TOKEN\n    description: Token for the legacy billing API\n    owner: billing-team\n"));
+}
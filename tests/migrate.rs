@@ -0,0 +1,188 @@
+mod common;
+
+use common::{amber_file, stderr, success, temp_copy, AMBER_YAML, SECRET_KEY};
+use std::path::Path;
+
+fn print_json(amber_yaml: &Path) -> String {
+    success(
+        amber_file(amber_yaml)
+            .arg("print")
+            .arg("--style")
+            .arg("json")
+            .env("AMBER_SECRET", SECRET_KEY)
+            .output()
+            .unwrap(),
+    )
+}
+
+fn read_yaml(amber_yaml: &Path) -> serde_yaml::Value {
+    serde_yaml::from_str(&std::fs::read_to_string(amber_yaml).unwrap()).unwrap()
+}
+
+#[test]
+fn migrate_from_version_1() {
+    let temp = temp_copy(AMBER_YAML);
+    let before = print_json(&temp);
+
+    let output = amber_file(&temp).arg("migrate").output().unwrap();
+    assert!(!output.status.success(), "migrate requires a secret key");
+
+    let output = amber_file(&temp)
+        .arg("migrate")
+        .env("AMBER_SECRET", SECRET_KEY)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(print_json(&temp), before);
+
+    let yaml = read_yaml(&temp);
+    assert_eq!(yaml["file_format_version"], 5);
+    for secret in yaml["secrets"].as_sequence().unwrap() {
+        assert!(secret.get("sha256").is_none());
+        assert!(secret.get("digest").is_some());
+    }
+}
+
+#[test]
+fn keyed_digest_change_detection() {
+    let temp = temp_copy(AMBER_YAML);
+    let status = amber_file(&temp)
+        .arg("migrate")
+        .env("AMBER_SECRET", SECRET_KEY)
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    let digest = |name: &str| {
+        read_yaml(&temp)["secrets"]
+            .as_sequence()
+            .unwrap()
+            .iter()
+            .find(|secret| secret["name"] == name)
+            .unwrap()
+            .get("digest")
+            .cloned()
+    };
+    let cipher = |name: &str| {
+        read_yaml(&temp)["secrets"]
+            .as_sequence()
+            .unwrap()
+            .iter()
+            .find(|secret| secret["name"] == name)
+            .unwrap()["ciphers"]
+            .clone()
+    };
+
+    // With the secret key, the digest is computed and unchanged values are left alone
+    let status = amber_file(&temp)
+        .arg("encrypt")
+        .arg("NEW")
+        .arg("value")
+        .env("AMBER_SECRET", SECRET_KEY)
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let new_digest = digest("NEW");
+    assert!(new_digest.is_some());
+    let new_cipher = cipher("NEW");
+    let output = amber_file(&temp)
+        .arg("encrypt")
+        .arg("NEW")
+        .arg("value")
+        .env("AMBER_SECRET", SECRET_KEY)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert!(stderr(&output).contains("New value matches old value"));
+    assert_eq!(cipher("NEW"), new_cipher);
+
+    // Without it, the value is still encrypted, just without a digest
+    let status = amber_file(&temp)
+        .arg("encrypt")
+        .arg("NEW")
+        .arg("value")
+        .status()
+        .unwrap();
+    assert!(status.success());
+    assert_eq!(digest("NEW"), None);
+    assert!(print_json(&temp).contains("\"NEW\""));
+
+    // Once the key is available again, the missing digest is filled in
+    let new_cipher = cipher("NEW");
+    let output = amber_file(&temp)
+        .arg("encrypt")
+        .arg("NEW")
+        .arg("value")
+        .env("AMBER_SECRET", SECRET_KEY)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert!(stderr(&output).contains("only adding its digest"));
+    assert_eq!(cipher("NEW"), new_cipher);
+    assert!(digest("NEW").is_some());
+}
+
+#[test]
+fn check_and_partial_migration() {
+    let temp = temp_copy(AMBER_YAML);
+    let output = amber_file(&temp)
+        .arg("migrate")
+        .arg("--check")
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("file format version 1"));
+
+    // Without a secret key, only the upgrades not requiring re-encryption are applied
+    let output = amber_file(&temp).arg("migrate").output().unwrap();
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("please provide a secret key"));
+    assert_eq!(read_yaml(&temp)["file_format_version"], 2);
+    assert!(print_json(&temp).contains("\"FOO\""));
+
+    let status = amber_file(&temp)
+        .arg("migrate")
+        .env("AMBER_SECRET", SECRET_KEY)
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let status = amber_file(&temp)
+        .arg("migrate")
+        .arg("--check")
+        .status()
+        .unwrap();
+    assert!(status.success());
+}
+
+#[test]
+fn newer_file_format() {
+    let temp = temp_copy(AMBER_YAML);
+    let contents = std::fs::read_to_string(&temp)
+        .unwrap()
+        .replace("file_format_version: 1", "file_format_version: 99");
+    std::fs::write(&temp, contents).unwrap();
+    let output = amber_file(&temp)
+        .arg("migrate")
+        .arg("--check")
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("newer version of amber"));
+}
+
+#[test]
+fn metadata_and_binary_values_require_version_5() {
+    let temp = temp_copy(AMBER_YAML);
+    let original = std::fs::read_to_string(&temp).unwrap();
+    for (field, error) in [
+        ("description: Added by hand", "contain secret metadata"),
+        ("kind: binary", "contain binary values"),
+    ] {
+        let contents =
+            original.replace("  - name: BAR\n", &format!("  - name: BAR\n    {field}\n"));
+        std::fs::write(&temp, contents).unwrap();
+        let output = amber_file(&temp).arg("list").output().unwrap();
+        assert!(!output.status.success());
+        assert!(stderr(&output).contains(error), "{}", stderr(&output));
+    }
+}
@@ -0,0 +1,132 @@
+mod common;
+
+use common::{get_vars, Pair};
+use std::path::Path;
+
+/// Run `amber init` in a fresh file, returning the secret key and public key
+fn new_keypair(dir: &Path, recipient: &str) -> (String, String) {
+    let amber_yaml = dir.join(format!("{recipient}.yaml"));
+    let output = common::amber()
+        .arg("init")
+        .arg("--only-secret-key")
+        .arg("--recipient")
+        .arg(recipient)
+        .env("AMBER_YAML", &amber_yaml)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let secret_key = String::from_utf8(output.stdout).unwrap();
+    let yaml: serde_yaml::Value =
+        serde_yaml::from_str(&std::fs::read_to_string(&amber_yaml).unwrap()).unwrap();
+    let public_key = yaml["recipients"][0]["public_key"]
+        .as_str()
+        .unwrap()
+        .to_owned();
+    (secret_key, public_key)
+}
+
+#[test]
+fn every_recipient_can_decrypt() {
+    let dir = tempfile::tempdir().unwrap();
+    let (alice_secret, alice_public) = new_keypair(dir.path(), "alice");
+    let (ci_secret, ci_public) = new_keypair(dir.path(), "ci");
+    let amber_yaml = dir.path().join("amber.yaml");
+    std::fs::write(
+        &amber_yaml,
+        format!(
+            "file_format_version: 2\nrecipients:\n- name: alice\n  public_key: {alice_public}\n- name: ci\n  public_key: {ci_public}\nsecrets: []\n"
+        ),
+    )
+    .unwrap();
+
+    let status = common::amber()
+        .arg("encrypt")
+        .arg("FOO")
+        .arg("foovalue")
+        .env("AMBER_YAML", &amber_yaml)
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    let expected = vec![Pair {
+        key: "FOO".to_owned(),
+        value: "foovalue".to_owned(),
+    }];
+    assert_eq!(get_vars(&amber_yaml, &alice_secret), expected);
+    assert_eq!(get_vars(&amber_yaml, &ci_secret), expected);
+
+    let (stranger_secret, _) = new_keypair(dir.path(), "stranger");
+    let status = common::amber()
+        .arg("print")
+        .env("AMBER_YAML", &amber_yaml)
+        .env("AMBER_SECRET", stranger_secret)
+        .status()
+        .unwrap();
+    assert!(!status.success());
+}
+
+#[test]
+fn add_and_remove_recipient() {
+    let dir = tempfile::tempdir().unwrap();
+    let amber_yaml = dir.path().join("amber.yaml");
+    let output = common::amber()
+        .arg("init")
+        .arg("--only-secret-key")
+        .arg("--recipient")
+        .arg("alice")
+        .env("AMBER_YAML", &amber_yaml)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let alice_secret = String::from_utf8(output.stdout).unwrap();
+
+    let status = common::amber()
+        .arg("encrypt")
+        .arg("FOO")
+        .arg("foovalue")
+        .env("AMBER_YAML", &amber_yaml)
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    let output = common::amber()
+        .arg("recipient")
+        .arg("add")
+        .arg("ci")
+        .arg("--only-secret-key")
+        .env("AMBER_YAML", &amber_yaml)
+        .env("AMBER_SECRET", &alice_secret)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert!(String::from_utf8(output.stderr)
+        .unwrap()
+        .contains("Re-encrypted FOO"));
+    let ci_secret = String::from_utf8(output.stdout).unwrap();
+
+    let expected = vec![Pair {
+        key: "FOO".to_owned(),
+        value: "foovalue".to_owned(),
+    }];
+    assert_eq!(get_vars(&amber_yaml, &alice_secret), expected);
+    assert_eq!(get_vars(&amber_yaml, &ci_secret), expected);
+
+    let status = common::amber()
+        .arg("recipient")
+        .arg("remove")
+        .arg("alice")
+        .env("AMBER_YAML", &amber_yaml)
+        .env("AMBER_SECRET", &ci_secret)
+        .status()
+        .unwrap();
+    assert!(status.success());
+    assert_eq!(get_vars(&amber_yaml, &ci_secret), expected);
+
+    let status = common::amber()
+        .arg("print")
+        .env("AMBER_YAML", &amber_yaml)
+        .env("AMBER_SECRET", &alice_secret)
+        .status()
+        .unwrap();
+    assert!(!status.success());
+}
@@ -0,0 +1,155 @@
+mod common;
+
+use common::{failure, run_amber, success};
+use std::path::Path;
+
+fn init(amber_yaml: &Path) -> String {
+    success(run_amber(amber_yaml, None, &["init", "--only-secret-key"]))
+        .trim()
+        .to_owned()
+}
+
+#[test]
+fn rename_secrets() {
+    let dir = tempfile::tempdir().unwrap();
+    let amber_yaml = dir.path().join("amber.yaml");
+    let key = init(&amber_yaml);
+    let run = |args: &[&str]| success(run_amber(&amber_yaml, Some(&key), args));
+    run(&[
+        "encrypt",
+        "API_KEY",
+        "secret",
+        "--description",
+        "Payment API",
+    ]);
+    run(&["encrypt", "DB_URL", "db"]);
+
+    let stderr = failure(run_amber(
+        &amber_yaml,
+        None,
+        &["rename", "API_KEY", "PAYMENT_KEY"],
+    ));
+    assert!(stderr.contains("requires the secret key"), "{}", stderr);
+    let stderr = failure(run_amber(
+        &amber_yaml,
+        Some(&key),
+        &["rename", "API_KEY", "DB_URL"],
+    ));
+    assert!(stderr.contains("DB_URL already exists"), "{}", stderr);
+    let stderr = failure(run_amber(
+        &amber_yaml,
+        Some(&key),
+        &["rename", "MISSING", "OTHER"],
+    ));
+    assert!(stderr.contains("Key does not exist: MISSING"), "{}", stderr);
+
+    run(&["rename", "API_KEY", "PAYMENT_KEY"]);
+    assert_eq!(
+        run(&["list", "--long"])
+            .lines()
+            .filter(|line| !line.contains(" created: ") && !line.contains(" updated: "))
+            .collect::<Vec<_>>(),
+        ["DB_URL", "PAYMENT_KEY", "    description: Payment API"]
+    );
+    assert_eq!(run(&["get", "PAYMENT_KEY"]), "secret\n");
+    // The renamed value is still recognized as unchanged
+    let stderr = String::from_utf8(
+        run_amber(
+            &amber_yaml,
+            Some(&key),
+            &["encrypt", "PAYMENT_KEY", "secret"],
+        )
+        .stderr,
+    )
+    .unwrap();
+    assert!(stderr.contains("doing nothing"), "{}", stderr);
+}
+
+#[test]
+fn copy_secrets() {
+    let dir = tempfile::tempdir().unwrap();
+    let staging = dir.path().join("staging.yaml");
+    let prod = dir.path().join("prod.yaml");
+    let staging_key = init(&staging);
+    let prod_key = init(&prod);
+    success(run_amber(
+        &staging,
+        Some(&staging_key),
+        &["encrypt", "API_KEY", "secret", "--owner", "payments"],
+    ));
+
+    let stderr = failure(run_amber(
+        &staging,
+        Some(&staging_key),
+        &["copy", "API_KEY"],
+    ));
+    assert!(stderr.contains("--to"), "{}", stderr);
+    success(run_amber(
+        &staging,
+        Some(&staging_key),
+        &["copy", "API_KEY", "--to", prod.to_str().unwrap()],
+    ));
+    assert_eq!(
+        success(run_amber(&prod, Some(&prod_key), &["get", "API_KEY"])),
+        "secret\n"
+    );
+    assert!(success(run_amber(&prod, None, &["list", "--long"])).contains("owner: payments"));
+    failure(run_amber(&prod, Some(&staging_key), &["get", "API_KEY"]));
+
+    // Between environments of the same file
+    let env_key = success(run_amber(
+        &staging,
+        Some(&staging_key),
+        &["env", "add", "canary", "--only-secret-key"],
+    ))
+    .trim()
+    .to_owned();
+    success(run_amber(
+        &staging,
+        Some(&staging_key),
+        &["encrypt", "API_KEY", "updated"],
+    ));
+    success(run_amber(
+        &staging,
+        Some(&staging_key),
+        &["copy", "API_KEY", "--to-env", "canary"],
+    ));
+    success(run_amber(
+        &staging,
+        Some(&staging_key),
+        &["encrypt", "API_KEY", "newer"],
+    ));
+    assert_eq!(
+        success(run_amber(
+            &staging,
+            Some(&env_key),
+            &["--env", "canary", "get", "API_KEY"]
+        )),
+        "updated\n"
+    );
+}
+
+#[test]
+fn rename_in_version_3_files() {
+    let dir = tempfile::tempdir().unwrap();
+    let amber_yaml = dir.path().join("amber.yaml");
+    std::fs::copy("assets/amber-v3.yaml", &amber_yaml).unwrap();
+    let key = "beff070c4e39ae1eb56c45cdda904b439294e06432c6d07012529bc1257503a5";
+    // The key of another file is an error, not a reason to carry on without one
+    let other_key = "ac2af4852f3de2dc6feb19b718d1cbf6c64c1ef618dafaf2b0a89cadcde240ac";
+    let stderr = failure(run_amber(
+        &amber_yaml,
+        Some(other_key),
+        &["rename", "FOO", "QUX"],
+    ));
+    assert!(stderr.contains("requires the secret key"), "{}", stderr);
+
+    success(run_amber(&amber_yaml, Some(key), &["rename", "FOO", "QUX"]));
+    assert_eq!(
+        success(run_amber(&amber_yaml, Some(key), &["get", "QUX"])),
+        "secret1\n"
+    );
+    assert!(std::fs::read_to_string(&amber_yaml)
+        .unwrap()
+        .starts_with("file_format_version: 3\n"));
+}
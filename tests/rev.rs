@@ -0,0 +1,108 @@
+mod common;
+
+use common::{amber_in, git, success};
+
+#[test]
+fn read_from_git_revisions() {
+    let dir = tempfile::tempdir().unwrap();
+    let dir = dir.path();
+    git(dir, &["init", "--quiet"]);
+    let secret_key = success(
+        amber_in(dir)
+            .args(["init", "--only-secret-key"])
+            .output()
+            .unwrap(),
+    );
+    let run = |args: &[&str]| {
+        success(
+            amber_in(dir)
+                .env("AMBER_SECRET", &secret_key)
+                .args(args)
+                .output()
+                .unwrap(),
+        )
+    };
+
+    run(&["encrypt", "API_KEY", "first"]);
+    git(dir, &["add", "-A"]);
+    git(dir, &["commit", "--quiet", "-m", "first"]);
+    run(&["encrypt", "API_KEY", "second"]);
+    git(dir, &["commit", "--quiet", "-am", "second"]);
+    run(&["convert", "directory"]);
+    run(&["encrypt", "DB_URL", "db"]);
+    git(dir, &["add", "-A"]);
+    git(dir, &["commit", "--quiet", "-m", "directory"]);
+    run(&["encrypt", "API_KEY", "uncommitted"]);
+    let status = git(dir, &["status", "--porcelain"]);
+
+    // Relative paths are resolved from the current directory, like in the working tree
+    let subdir = dir.join("src");
+    std::fs::create_dir(&subdir).unwrap();
+    let print = |rev: &str| {
+        success(
+            amber_in(&subdir)
+                .env("AMBER_SECRET", &secret_key)
+                .args(["--rev", rev, "print"])
+                .output()
+                .unwrap(),
+        )
+    };
+    assert_eq!(print("HEAD~2"), "export API_KEY='first'\n");
+    assert_eq!(
+        print("HEAD"),
+        "export API_KEY='second'\nexport DB_URL='db'\n"
+    );
+
+    assert_eq!(run(&["--rev", "HEAD~2", "get", "API_KEY"]), "first\n");
+    let output = run(&["--rev", "HEAD~2", "exec", "sh", "--", "-c", "echo $API_KEY"]);
+    assert_eq!(output, "******\n");
+    let dest = dir.join("api-key");
+    success(
+        amber_in(dir)
+            .env("AMBER_SECRET", &secret_key)
+            .args([
+                "--rev",
+                "HEAD~1",
+                "write-file",
+                "--key",
+                "API_KEY",
+                "--dest",
+            ])
+            .arg(&dest)
+            .output()
+            .unwrap(),
+    );
+    assert_eq!(std::fs::read_to_string(&dest).unwrap(), "second");
+    std::fs::remove_file(dest).unwrap();
+
+    // Only reading is supported, and the working tree is left alone
+    for args in [
+        &["encrypt", "API_KEY", "new"][..],
+        &["init"],
+        &["convert", "file"],
+        &["textconv", "amber.yaml"],
+        &["diff", "HEAD:", ".amber"],
+        &["merge-driver", "base", "ours", "theirs"],
+    ] {
+        let output = amber_in(dir)
+            .env("AMBER_SECRET", &secret_key)
+            .args(["--rev", "HEAD"])
+            .args(args)
+            .output()
+            .unwrap();
+        assert!(!output.status.success());
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        assert!(stderr.contains("--rev is only supported"), "{}", stderr);
+    }
+    let output = amber_in(dir)
+        .env("AMBER_SECRET", &secret_key)
+        .args(["--rev", "no-such-branch", "print"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert_eq!(git(dir, &["status", "--porcelain"]), status);
+    assert_eq!(
+        run(&["print"]),
+        "export API_KEY='uncommitted'\nexport DB_URL='db'\n"
+    );
+}
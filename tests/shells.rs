@@ -0,0 +1,272 @@
+mod common;
+
+use common::{amber_with_key, success, without_amber_vars, AMBER_YAML, SECRET_KEY};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Values shells would expand or choke on if not quoted properly
+const VALUES: &[(&str, &str)] = &[
+    ("EXPANSION", "$HOME ${PATH} `id` $(id) %PATH% !bang! ~"),
+    ("QUOTES", r#"it's "double" ‘smart’ '' \' \" end\"#),
+    ("BACKSLASHES", r"C:\path\n \\ \t \u{41} \x41"),
+    ("HASHES", "'# '## '### #comment"),
+    ("MULTILINE", "first\nsecond\r\n\tthird\n"),
+    ("UNICODE", "é ✓ 🔑 \u{1b}[0m"),
+    ("SPACES", "  leading and trailing  "),
+    ("EMPTY", ""),
+];
+
+/// An amber file holding the tricky values, along with those of the test file
+fn setup(dir: &Path, values: &[(&str, &str)]) -> PathBuf {
+    let amber_yaml = dir.join("amber.yaml");
+    std::fs::copy(AMBER_YAML, &amber_yaml).unwrap();
+    for (name, value) in values {
+        success(
+            amber_with_key(&amber_yaml, SECRET_KEY)
+                .args(["encrypt", name, value])
+                .output()
+                .unwrap(),
+        );
+    }
+    amber_yaml
+}
+
+fn print(amber_yaml: &Path, style: &str) -> String {
+    success(
+        amber_with_key(amber_yaml, SECRET_KEY)
+            .args(["print", "--style", style])
+            .output()
+            .unwrap(),
+    )
+}
+
+#[test]
+fn print_styles() {
+    let dir = tempfile::tempdir().unwrap();
+    let amber_yaml = setup(
+        dir.path(),
+        &[("QUOTES", "it's \\ ’"), ("HASHES", "'# $x 50%")],
+    );
+    let output = |style| {
+        print(&amber_yaml, style)
+            .lines()
+            .filter(|line| line.contains("QUOTES") || line.contains("HASHES"))
+            .collect::<Vec<_>>()
+            .join("\n")
+    };
+    assert_eq!(
+        output("setenv"),
+        r#"export HASHES=''\''# $x 50%'
+export QUOTES='it'\''s \ ’'"#
+    );
+    assert_eq!(
+        output("fish"),
+        r#"set -gx HASHES '\'# $x 50%'
+set -gx QUOTES 'it\'s \\ ’'"#
+    );
+    assert_eq!(
+        output("powershell"),
+        r#"$env:HASHES = '''# $x 50%'
+$env:QUOTES = 'it''s \ ’’'"#
+    );
+    assert_eq!(
+        output("nushell"),
+        r#"$env.HASHES = r##''# $x 50%'##
+$env.QUOTES = r#'it's \ ’'#"#
+    );
+    assert_eq!(
+        output("cmd"),
+        r#"set "HASHES='# $x 50%%"
+set "QUOTES=it's \ ’""#
+    );
+
+    success(
+        amber_with_key(&amber_yaml, SECRET_KEY)
+            .args(["encrypt", "MULTILINE", "first\nsecond"])
+            .output()
+            .unwrap(),
+    );
+    let output = amber_with_key(&amber_yaml, SECRET_KEY)
+        .args(["print", "--style", "cmd"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(output.stdout.is_empty());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.contains("MULTILINE contains a line break"),
+        "{}",
+        stderr
+    );
+
+    // A double quote would end the quoted part, running the rest of the value
+    success(
+        amber_with_key(&amber_yaml, SECRET_KEY)
+            .args(["remove", "MULTILINE"])
+            .output()
+            .unwrap(),
+    );
+    success(
+        amber_with_key(&amber_yaml, SECRET_KEY)
+            .args(["encrypt", "INJECTED", "a\"&echo PWNED&\""])
+            .output()
+            .unwrap(),
+    );
+    let output = amber_with_key(&amber_yaml, SECRET_KEY)
+        .args(["print", "--style", "cmd"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(output.stdout.is_empty());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.contains("INJECTED contains a double quote"),
+        "{}",
+        stderr
+    );
+}
+
+/// Find an executable on the PATH
+#[cfg(unix)]
+fn which(name: &str) -> Option<PathBuf> {
+    std::env::split_paths(&std::env::var_os("PATH")?)
+        .map(|dir| dir.join(name))
+        .find(|path| path.is_file())
+}
+
+/// Parse the output of `env -0`
+#[cfg(unix)]
+fn environment(output: std::process::Output) -> BTreeMap<String, String> {
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(output.status.success(), "{}", stderr);
+    String::from_utf8(output.stdout)
+        .unwrap()
+        .split('\0')
+        .filter_map(|entry| entry.split_once('='))
+        .map(|(name, value)| (name.to_owned(), value.to_owned()))
+        .collect()
+}
+
+/// Evaluate the output of each style in its shell, and compare the variables set
+///
+/// Shells that aren't installed are skipped, except on CI, which installs all of them.
+#[cfg(unix)]
+#[test]
+fn print_round_trips_through_shells() {
+    let dir = tempfile::tempdir().unwrap();
+    let amber_yaml = setup(dir.path(), VALUES);
+    let env = which("env").unwrap();
+    let env = env.to_str().unwrap();
+    let mut expected: BTreeMap<String, String> = VALUES
+        .iter()
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect();
+    expected.insert("BAR".to_owned(), "SECRET123".to_owned());
+    expected.insert("BAZ".to_owned(), "something".to_owned());
+    expected.insert("FOO".to_owned(), "secret1".to_owned());
+
+    let shells: &[(&str, &[&str], &str, String)] = &[
+        ("sh", &[], "setenv", format!("{env} -0")),
+        ("bash", &["--norc"], "setenv", format!("{env} -0")),
+        ("dash", &[], "setenv", format!("{env} -0")),
+        ("zsh", &["-f"], "setenv", format!("{env} -0")),
+        ("fish", &["--no-config"], "fish", format!("{env} -0")),
+        (
+            "pwsh",
+            &["-NoProfile", "-File"],
+            "powershell",
+            format!("& '{env}' -0"),
+        ),
+        (
+            "nu",
+            &["--no-config-file"],
+            "nushell",
+            format!("^'{env}' -0"),
+        ),
+    ];
+    let required = std::env::var_os("CI").is_some();
+    let mut tested = Vec::new();
+    for (shell, args, style, dump) in shells {
+        let Some(path) = which(shell) else {
+            assert!(
+                !required,
+                "{} is not installed, CI must test every shell",
+                shell
+            );
+            eprintln!("{shell} is not installed, skipping it");
+            continue;
+        };
+        let script = dir.path().join(format!("{shell}.{style}"));
+        let script = match *style {
+            "powershell" => script.with_extension("ps1"),
+            _ => script,
+        };
+        std::fs::write(&script, format!("{}{dump}\n", print(&amber_yaml, style))).unwrap();
+        let output = Command::new(path)
+            .args(*args)
+            .arg(&script)
+            .output()
+            .unwrap();
+        let mut actual = environment(output);
+        actual.retain(|name, _| expected.contains_key(name));
+        assert_eq!(actual, expected, "{shell}");
+        tested.push(shell);
+    }
+    assert!(tested.contains(&&"sh"));
+
+    // Also when evaluated from a command substitution, as in the README
+    let output = without_amber_vars(Command::new("sh"))
+        .arg("-c")
+        .arg(format!("eval \"$(\"$0\" print)\"; {env} -0"))
+        .arg(assert_cmd::cargo::cargo_bin("amber"))
+        .env("AMBER_YAML", &amber_yaml)
+        .env("AMBER_SECRET", SECRET_KEY)
+        .output()
+        .unwrap();
+    let mut actual = environment(output);
+    actual.retain(|name, _| expected.contains_key(name));
+    assert_eq!(actual, expected);
+}
+
+/// Run the cmd style as a batch file in cmd.exe, and compare the variables set
+#[cfg(windows)]
+#[test]
+fn print_round_trips_through_cmd() {
+    let dir = tempfile::tempdir().unwrap();
+    // cmd refuses line breaks and double quotes, and setting an empty value removes the variable
+    let values: Vec<(&str, &str)> = VALUES
+        .iter()
+        .copied()
+        .filter(|(_, value)| !value.is_empty() && !value.contains(['\n', '\r', '"']))
+        .collect();
+    let amber_yaml = setup(dir.path(), &values);
+    let mut expected: BTreeMap<String, String> = values
+        .iter()
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect();
+    expected.insert("BAR".to_owned(), "SECRET123".to_owned());
+    expected.insert("BAZ".to_owned(), "something".to_owned());
+    expected.insert("FOO".to_owned(), "secret1".to_owned());
+
+    // Read the script as UTF-8, then list every variable with set
+    let script = dir.path().join("secrets.bat");
+    let contents = format!(
+        "@echo off\nchcp 65001 > nul\n{}set\n",
+        print(&amber_yaml, "cmd")
+    );
+    std::fs::write(&script, contents.replace('\n', "\r\n")).unwrap();
+    let output = Command::new("cmd")
+        .args(["/d", "/c"])
+        .arg(&script)
+        .output()
+        .unwrap();
+    let stdout = success(output);
+    let actual: BTreeMap<String, String> = stdout
+        .lines()
+        .filter_map(|line| line.split_once('='))
+        .filter(|(name, _)| expected.contains_key(*name))
+        .map(|(name, value)| (name.to_owned(), value.to_owned()))
+        .collect();
+    assert_eq!(actual, expected);
+}
@@ -0,0 +1,54 @@
+mod common;
+
+use common::amber_file;
+
+#[test]
+fn swapped_ciphertexts_are_rejected() {
+    let dir = tempfile::tempdir().unwrap();
+    let amber_yaml = dir.path().join("amber.yaml");
+    let output = amber_file(&amber_yaml)
+        .arg("init")
+        .arg("--only-secret-key")
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let secret_key = String::from_utf8(output.stdout).unwrap();
+
+    for (key, value) in [
+        ("DB_PASSWORD", "hunter2"),
+        ("PUBLIC_URL", "https://example.com"),
+    ] {
+        let status = amber_file(&amber_yaml)
+            .arg("encrypt")
+            .arg(key)
+            .arg(value)
+            .status()
+            .unwrap();
+        assert!(status.success());
+    }
+
+    let status = amber_file(&amber_yaml)
+        .arg("print")
+        .env("AMBER_SECRET", &secret_key)
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    let mut yaml: serde_yaml::Value =
+        serde_yaml::from_str(&std::fs::read_to_string(&amber_yaml).unwrap()).unwrap();
+    let secrets = yaml["secrets"].as_sequence_mut().unwrap();
+    let first = secrets[0]["name"].clone();
+    let second = secrets[1]["name"].clone();
+    secrets[0]["name"] = second;
+    secrets[1]["name"] = first;
+    std::fs::write(&amber_yaml, serde_yaml::to_string(&yaml).unwrap()).unwrap();
+
+    let output = amber_file(&amber_yaml)
+        .arg("print")
+        .env("AMBER_SECRET", &secret_key)
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("tampered"), "{}", stderr);
+}
@@ -0,0 +1,45 @@
+mod common;
+
+use common::{amber_with_key, success, temp_copy, AMBER_YAML, SECRET_KEY};
+use std::path::Path;
+
+fn textconv(file: &Path, secret_key: Option<&str>, args: &[&str]) -> String {
+    let mut cmd = common::amber();
+    cmd.arg("textconv").arg(file).args(args);
+    if let Some(secret_key) = secret_key {
+        cmd.env("AMBER_SECRET", secret_key);
+    }
+    success(cmd.output().unwrap())
+}
+
+#[test]
+fn textconv_renders_values() {
+    let temp = temp_copy(AMBER_YAML);
+    let status = amber_with_key(&temp, SECRET_KEY)
+        .args(["encrypt", "MULTILINE", "first\nsecond"])
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    assert_eq!(
+        textconv(&temp, Some(SECRET_KEY), &[]),
+        "# recipients: default\nBAR=SECRET123\nBAZ=something\nFOO=secret1\nMULTILINE=first\\nsecond\n"
+    );
+
+    let hashed = textconv(&temp, Some(SECRET_KEY), &["--values", "hashed"]);
+    assert!(!hashed.contains("SECRET123"));
+    assert!(hashed.contains("BAR=hmac:"));
+    assert_eq!(
+        hashed,
+        textconv(&temp, Some(SECRET_KEY), &["--values", "hashed"])
+    );
+
+    // Without a usable key, changes still show through the ciphertexts
+    let masked = textconv(&temp, None, &[]);
+    assert!(masked.contains("BAR=encrypted:"));
+    assert_eq!(textconv(&temp, Some(&"11".repeat(32)), &[]), masked);
+    assert_eq!(
+        textconv(&temp, Some(SECRET_KEY), &["--values", "masked"]),
+        masked
+    );
+}